@@ -0,0 +1,571 @@
+//! Body parser module for the Treblle middleware.
+//!
+//! This module turns raw request and response bodies into a JSON representation
+//! based on their content type, so the same masking rules can be applied to them.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::charset::decode_text;
+use crate::constants::{MAX_XML_DEPTH, XML_DEPTH_EXCEEDED};
+use crate::error::{Result, TreblleError};
+
+const FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+
+/// How a body is represented in the Treblle payload.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BodyKind {
+    /// Parsed as JSON, including `+json` media types.
+    Json,
+    /// URL-encoded and multipart forms, parsed into an object.
+    Form,
+    /// Captured as a plain string.
+    Text,
+    /// Converted into a JSON tree.
+    Xml,
+    /// Replaced by its content type and size.
+    Binary,
+}
+
+impl BodyKind {
+    /// Parses a `captureContentTypes` entry.
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Some(BodyKind::Json),
+            "form" => Some(BodyKind::Form),
+            "text" => Some(BodyKind::Text),
+            "xml" => Some(BodyKind::Xml),
+            "binary" => Some(BodyKind::Binary),
+            _ => None,
+        }
+    }
+
+    /// Classifies a `Content-Type` header value.
+    ///
+    /// A missing content type is treated as JSON, since a JSON body that cannot be
+    /// parsed is simply reported as `null`.
+    pub fn from_content_type(content_type: &str) -> Self {
+        let mime = media_type(content_type);
+
+        if mime.is_empty() || is_json(&mime) {
+            BodyKind::Json
+        } else if is_urlencoded(&mime) || is_multipart(&mime) {
+            BodyKind::Form
+        } else if mime == "application/xml" || mime == "text/xml" || mime.ends_with("+xml") {
+            BodyKind::Xml
+        } else if mime.starts_with("text/")
+            || mime == "application/graphql"
+            || mime == "application/javascript"
+        {
+            BodyKind::Text
+        } else {
+            BodyKind::Binary
+        }
+    }
+}
+
+/// Parses a body into a JSON value, reporting why it could not be decoded.
+///
+/// Text-based bodies are transcoded to UTF-8 according to their declared charset first.
+///
+/// # Arguments
+///
+/// * `body` - The raw body to parse.
+/// * `content_type` - The value of the `Content-Type` header.
+///
+/// # Returns
+///
+/// Returns a `Result<Value>`, with `Value::Null` for empty bodies, or an error describing
+/// the charset, JSON or XML problem.
+pub fn try_parse_body(body: &[u8], content_type: &str) -> Result<Value> {
+    if body.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    match BodyKind::from_content_type(content_type) {
+        BodyKind::Json => Ok(serde_json::from_str(&decode_text(body, content_type)?)?),
+        BodyKind::Form if is_multipart(content_type) => Ok(match extract_boundary(content_type) {
+            Some(boundary) => parse_multipart_body(body, &boundary),
+            None => Value::Null,
+        }),
+        BodyKind::Form => Ok(parse_urlencoded_body(
+            decode_text(body, content_type)?.as_bytes(),
+        )),
+        BodyKind::Text => Ok(Value::String(decode_text(body, content_type)?.into_owned())),
+        BodyKind::Xml => match parse_xml_body(decode_text(body, content_type)?.as_bytes()) {
+            Value::Null => Err(TreblleError::Xml("Malformed XML body".to_string())),
+            parsed => Ok(parsed),
+        },
+        BodyKind::Binary => Ok(binary_metadata(body, content_type)),
+    }
+}
+
+/// Checks if a content type is JSON, including structured syntax suffixes such as
+/// `application/problem+json`.
+pub fn is_json(content_type: &str) -> bool {
+    let mime = media_type(content_type);
+    mime == "application/json" || mime == "text/json" || mime.ends_with("+json")
+}
+
+/// Checks if a content type is `application/x-www-form-urlencoded`.
+pub fn is_urlencoded(content_type: &str) -> bool {
+    content_type.to_lowercase().contains(FORM_URLENCODED)
+}
+
+/// Checks if a content type is `multipart/form-data`.
+pub fn is_multipart(content_type: &str) -> bool {
+    content_type.to_lowercase().contains(MULTIPART_FORM_DATA)
+}
+
+/// Parses an `application/x-www-form-urlencoded` body into a JSON object.
+///
+/// Repeated keys are collected into an array.
+///
+/// # Arguments
+///
+/// * `body` - The raw body to parse.
+///
+/// # Returns
+///
+/// Returns a `Value::Object` with the decoded fields.
+pub fn parse_urlencoded_body(body: &[u8]) -> Value {
+    let mut fields = Map::new();
+
+    for (key, value) in url::form_urlencoded::parse(body) {
+        insert_field(
+            &mut fields,
+            key.into_owned(),
+            Value::String(value.into_owned()),
+        );
+    }
+
+    Value::Object(fields)
+}
+
+/// Parses a `multipart/form-data` body into a JSON object.
+///
+/// Text parts are reported as strings. File parts are replaced by their metadata
+/// (`name`, `filename`, `content_type`, `size`) so their content never leaves the proxy.
+///
+/// # Arguments
+///
+/// * `body` - The raw body to parse.
+/// * `boundary` - The boundary declared in the `Content-Type` header.
+///
+/// # Returns
+///
+/// Returns a `Value::Object` with the parsed parts, or `Value::Null` if no part could be found.
+pub fn parse_multipart_body(body: &[u8], boundary: &str) -> Value {
+    let delimiter = format!("--{}", boundary);
+    let mut fields = Map::new();
+
+    let mut rest = match find_subslice(body, delimiter.as_bytes()) {
+        Some(start) => &body[start + delimiter.len()..],
+        None => return Value::Null,
+    };
+
+    // Each iteration consumes one part; the closing delimiter is followed by `--`.
+    while !rest.starts_with(b"--") {
+        let part_end = match find_subslice(rest, delimiter.as_bytes()) {
+            Some(end) => end,
+            None => break,
+        };
+
+        let part = strip_crlf(&rest[..part_end]);
+        if let Some((name, value)) = parse_multipart_part(part) {
+            insert_field(&mut fields, name, value);
+        }
+
+        rest = &rest[part_end + delimiter.len()..];
+    }
+
+    if fields.is_empty() {
+        Value::Null
+    } else {
+        Value::Object(fields)
+    }
+}
+
+/// Converts an XML body into a JSON tree.
+///
+/// Elements become objects keyed by tag name, attributes are prefixed with `@`, and
+/// repeated elements are collected into arrays. Elements holding only text become strings;
+/// mixed content keeps its text under `#text`. Elements nested deeper than
+/// `MAX_XML_DEPTH` are replaced by a marker, so the tree stays shallow enough to mask.
+///
+/// # Arguments
+///
+/// * `body` - The raw XML body.
+///
+/// # Returns
+///
+/// Returns a `Value::Object` with the root element, or `Value::Null` if the XML is malformed.
+pub fn parse_xml_body(body: &[u8]) -> Value {
+    let mut reader = Reader::from_reader(body);
+    reader.config_mut().trim_text(true);
+
+    // Open elements: tag name, attributes and children, and accumulated text.
+    let mut stack: Vec<(String, Map<String, Value>, String)> = Vec::new();
+    let mut root = Map::new();
+    let mut buf = Vec::new();
+    // Open elements nested deeper than `MAX_XML_DEPTH`, whose content is skipped.
+    let mut skipped = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(_)) if skipped > 0 => skipped += 1,
+            Ok(Event::Start(start)) if stack.len() >= MAX_XML_DEPTH => {
+                let (name, _) = xml_element(&start);
+                if let Some((_, parent, _)) = stack.last_mut() {
+                    insert_field(parent, name, Value::String(XML_DEPTH_EXCEEDED.to_string()));
+                }
+                skipped = 1;
+            }
+            Ok(Event::Start(start)) => {
+                let (name, fields) = xml_element(&start);
+                stack.push((name, fields, String::new()));
+            }
+            Ok(Event::Empty(_)) if skipped > 0 => {}
+            Ok(Event::Empty(start)) => {
+                let (name, fields) = xml_element(&start);
+                let value = xml_value(fields, String::new());
+                match stack.last_mut() {
+                    Some((_, parent, _)) => insert_field(parent, name, value),
+                    None => insert_field(&mut root, name, value),
+                }
+            }
+            Ok(Event::Text(_) | Event::CData(_)) if skipped > 0 => {}
+            Ok(Event::Text(text)) => {
+                if let (Some((_, _, content)), Ok(text)) = (stack.last_mut(), text.unescape()) {
+                    content.push_str(&text);
+                }
+            }
+            Ok(Event::CData(data)) => {
+                if let Some((_, _, content)) = stack.last_mut() {
+                    content.push_str(&String::from_utf8_lossy(&data));
+                }
+            }
+            Ok(Event::End(_)) if skipped > 0 => skipped -= 1,
+            Ok(Event::End(_)) => {
+                let Some((name, fields, content)) = stack.pop() else {
+                    return Value::Null;
+                };
+                let value = xml_value(fields, content);
+                match stack.last_mut() {
+                    Some((_, parent, _)) => insert_field(parent, name, value),
+                    None => insert_field(&mut root, name, value),
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(_) => return Value::Null,
+        }
+        buf.clear();
+    }
+
+    if root.is_empty() || !stack.is_empty() {
+        Value::Null
+    } else {
+        Value::Object(root)
+    }
+}
+
+/// Extracts the `boundary` parameter from a multipart content type.
+pub fn extract_boundary(content_type: &str) -> Option<String> {
+    content_type
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Describes a binary body without including its content.
+fn binary_metadata(body: &[u8], content_type: &str) -> Value {
+    let mut metadata = Map::new();
+    metadata.insert(
+        "content_type".to_string(),
+        Value::String(media_type(content_type)),
+    );
+    metadata.insert("size".to_string(), Value::from(body.len()));
+    Value::Object(metadata)
+}
+
+/// Returns the lowercased media type of a content type, without its parameters.
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+/// Reads the tag name and attributes of an XML element.
+fn xml_element(start: &BytesStart) -> (String, Map<String, Value>) {
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+    let mut fields = Map::new();
+
+    for attribute in start.attributes().flatten() {
+        let key = format!("@{}", String::from_utf8_lossy(attribute.key.as_ref()));
+        let value = attribute
+            .unescape_value()
+            .map(|v| v.into_owned())
+            .unwrap_or_default();
+        fields.insert(key, Value::String(value));
+    }
+
+    (name, fields)
+}
+
+/// Builds the JSON value of a closed XML element.
+fn xml_value(mut fields: Map<String, Value>, content: String) -> Value {
+    if fields.is_empty() {
+        if content.is_empty() {
+            Value::Null
+        } else {
+            Value::String(content)
+        }
+    } else {
+        if !content.is_empty() {
+            fields.insert("#text".to_string(), Value::String(content));
+        }
+        Value::Object(fields)
+    }
+}
+
+/// Parses a single multipart part into its field name and JSON value.
+fn parse_multipart_part(part: &[u8]) -> Option<(String, Value)> {
+    let header_end = find_subslice(part, b"\r\n\r\n")?;
+    let raw_headers = String::from_utf8_lossy(&part[..header_end]);
+    let content = &part[header_end + 4..];
+
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+
+    for line in raw_headers.lines() {
+        let Some((header, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        if header.trim().eq_ignore_ascii_case("content-disposition") {
+            name = disposition_param(value, "name");
+            filename = disposition_param(value, "filename");
+        } else if header.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    let name = name?;
+
+    let value = match filename {
+        Some(filename) => {
+            let mut metadata = Map::new();
+            metadata.insert("name".to_string(), Value::String(name.clone()));
+            metadata.insert("filename".to_string(), Value::String(filename));
+            metadata.insert(
+                "content_type".to_string(),
+                content_type.map(Value::String).unwrap_or(Value::Null),
+            );
+            metadata.insert("size".to_string(), Value::from(content.len()));
+            Value::Object(metadata)
+        }
+        None => Value::String(String::from_utf8_lossy(content).into_owned()),
+    };
+
+    Some((name, value))
+}
+
+/// Reads a quoted parameter such as `name="field"` from a `Content-Disposition` value.
+fn disposition_param(disposition: &str, param: &str) -> Option<String> {
+    disposition
+        .split(';')
+        .filter_map(|part| part.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(param))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+/// Inserts a field, turning repeated keys into an array of values.
+fn insert_field(fields: &mut Map<String, Value>, key: String, value: Value) {
+    match fields.get_mut(&key) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None => {
+            fields.insert(key, value);
+        }
+    }
+}
+
+/// Removes the CRLF that surrounds a part between two delimiters.
+fn strip_crlf(part: &[u8]) -> &[u8] {
+    let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+    part.strip_suffix(b"\r\n").unwrap_or(part)
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_urlencoded_body() {
+        let parsed = parse_urlencoded_body(b"username=john&password=s%3Dcret&tag=a&tag=b");
+
+        assert_eq!(parsed["username"], "john");
+        assert_eq!(parsed["password"], "s=cret");
+        assert_eq!(parsed["tag"], serde_json::json!(["a", "b"]));
+    }
+
+    #[test]
+    fn test_parse_multipart_body() {
+        let body = b"--XyZ\r\n\
+Content-Disposition: form-data; name=\"password\"\r\n\r\n\
+hunter2\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n\
+Content-Type: image/png\r\n\r\n\
+\x89PNG\r\n\
+--XyZ--\r\n";
+
+        let parsed = parse_multipart_body(body, "XyZ");
+
+        assert_eq!(parsed["password"], "hunter2");
+        assert_eq!(parsed["avatar"]["filename"], "me.png");
+        assert_eq!(parsed["avatar"]["content_type"], "image/png");
+        assert_eq!(parsed["avatar"]["size"], 4);
+    }
+
+    #[test]
+    fn test_extract_boundary() {
+        assert_eq!(
+            extract_boundary("multipart/form-data; boundary=\"abc123\""),
+            Some("abc123".to_string())
+        );
+        assert_eq!(extract_boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_parse_body_by_content_type() -> Result<()> {
+        assert_eq!(try_parse_body(br#"{"a":1}"#, "application/json")?["a"], 1);
+        assert_eq!(
+            try_parse_body(br#"{"a":1}"#, "application/problem+json")?["a"],
+            1
+        );
+        assert_eq!(
+            try_parse_body(b"hello", "text/plain; charset=utf-8")?,
+            "hello"
+        );
+        assert_eq!(
+            try_parse_body(b"\x89PNG", "image/png")?,
+            serde_json::json!({ "content_type": "image/png", "size": 4 })
+        );
+        assert_eq!(try_parse_body(b"", "text/plain")?, Value::Null);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_parse_body_reports_errors() {
+        assert_eq!(
+            try_parse_body(
+                b"{\"name\":\"Jos\xe9\"}",
+                "application/json; charset=latin1"
+            )
+            .unwrap()["name"],
+            "José"
+        );
+        assert!(matches!(
+            try_parse_body(b"not json", "application/json"),
+            Err(TreblleError::Json(_))
+        ));
+        assert!(matches!(
+            try_parse_body(b"caf\xe9", "text/plain"),
+            Err(TreblleError::Charset(_))
+        ));
+        assert!(matches!(
+            try_parse_body(b"<a><b></a>", "application/xml"),
+            Err(TreblleError::Xml(_))
+        ));
+    }
+
+    #[test]
+    fn test_body_kind_from_content_type() {
+        assert_eq!(
+            BodyKind::from_content_type("application/vnd.api+json"),
+            BodyKind::Json
+        );
+        assert_eq!(BodyKind::from_content_type("text/xml"), BodyKind::Xml);
+        assert_eq!(
+            BodyKind::from_content_type("multipart/form-data; boundary=abc"),
+            BodyKind::Form
+        );
+        assert_eq!(
+            BodyKind::from_content_type("Application/X-WWW-Form-Urlencoded"),
+            BodyKind::Form
+        );
+        assert_eq!(
+            BodyKind::from_content_type("application/atom+xml"),
+            BodyKind::Xml
+        );
+        assert_eq!(
+            BodyKind::from_content_type("application/graphql"),
+            BodyKind::Text
+        );
+        assert_eq!(
+            BodyKind::from_content_type("application/octet-stream"),
+            BodyKind::Binary
+        );
+        assert_eq!(BodyKind::from_str("XML"), Some(BodyKind::Xml));
+        assert_eq!(BodyKind::from_str("yaml"), None);
+    }
+
+    #[test]
+    fn test_parse_deeply_nested_xml_body() {
+        let depth = MAX_XML_DEPTH * 100;
+        let body = format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+
+        let mut value = &parse_xml_body(body.as_bytes());
+        for _ in 0..MAX_XML_DEPTH {
+            value = &value["a"];
+        }
+
+        assert_eq!(value["a"], XML_DEPTH_EXCEEDED);
+        assert_eq!(
+            parse_xml_body(format!("{}</a>", "<a>".repeat(depth)).as_bytes()),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_parse_xml_body() {
+        let parsed = parse_xml_body(
+            br#"<?xml version="1.0"?>
+<user id="7">
+  <name>John</name>
+  <password>hunter2</password>
+  <role>admin</role>
+  <role>dev</role>
+  <avatar/>
+</user>"#,
+        );
+
+        assert_eq!(parsed["user"]["@id"], "7");
+        assert_eq!(parsed["user"]["name"], "John");
+        assert_eq!(parsed["user"]["password"], "hunter2");
+        assert_eq!(parsed["user"]["role"], serde_json::json!(["admin", "dev"]));
+        assert_eq!(parsed["user"]["avatar"], Value::Null);
+        assert_eq!(parse_xml_body(b"<user><name>John</user>"), Value::Null);
+    }
+}
@@ -0,0 +1,180 @@
+//! Capture policy module for the Treblle middleware.
+//!
+//! This module decides, once the response is known, whether a request is shipped to
+//! Treblle. With the `errors_or_slow` policy only failures and latency outliers are kept.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// Which requests are shipped to Treblle.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum CapturePolicy {
+    /// Ship every request that passes route filtering and sampling.
+    #[default]
+    All,
+    /// Hold request data until the response and only ship errors and slow requests.
+    ErrorsOrSlow,
+}
+
+impl CapturePolicy {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "errors_or_slow" | "errorsorslow" => CapturePolicy::ErrorsOrSlow,
+            _ => CapturePolicy::default(),
+        }
+    }
+}
+
+/// An inclusive range of HTTP status codes.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct StatusCodeRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl StatusCodeRange {
+    /// Reads a status code range from a number (`503`), a range (`"500-599"`) or a
+    /// class (`"4xx"`).
+    pub fn from_value(value: &Value) -> Option<Self> {
+        if let Some(code) = value.as_u64() {
+            let code = u32::try_from(code).ok()?;
+            return Some(StatusCodeRange {
+                start: code,
+                end: code,
+            });
+        }
+
+        let s = value.as_str()?.trim().to_lowercase();
+
+        let (start, end) = if let Some(class) = s.strip_suffix("xx") {
+            let class: u32 = class.parse().ok()?;
+            (class * 100, class * 100 + 99)
+        } else if let Some((start, end)) = s.split_once('-') {
+            (start.trim().parse().ok()?, end.trim().parse().ok()?)
+        } else {
+            let code = s.parse().ok()?;
+            (code, code)
+        };
+
+        (start <= end).then_some(StatusCodeRange { start, end })
+    }
+
+    /// Checks if a status code falls within the range.
+    pub fn contains(&self, status_code: u32) -> bool {
+        (self.start..=self.end).contains(&status_code)
+    }
+}
+
+/// Checks if a request should be shipped now that its response is known.
+///
+/// Under `errors_or_slow`, a request is shipped when its status code is in
+/// `captureStatusCodes` (any status >= 400 if none are configured), when Traefik reports
+/// an error, or when it took at least `slowRequestThresholdMs`.
+///
+/// # Arguments
+///
+/// * `config` - The middleware configuration.
+/// * `status_code` - The response status code.
+/// * `is_error` - Whether Traefik reported the response as an error.
+/// * `elapsed` - The time since the request was received.
+///
+/// # Returns
+///
+/// Returns `true` if the request should be shipped to Treblle.
+pub fn should_ship(config: &Config, status_code: u32, is_error: bool, elapsed: Duration) -> bool {
+    if config.capture_policy == CapturePolicy::All || is_error {
+        return true;
+    }
+
+    let status_matches = if config.capture_status_codes.is_empty() {
+        status_code >= 400
+    } else {
+        config
+            .capture_status_codes
+            .iter()
+            .any(|range| range.contains(status_code))
+    };
+
+    let is_slow = config.slow_request_threshold_ms > 0
+        && elapsed >= Duration::from_millis(config.slow_request_threshold_ms);
+
+    status_matches || is_slow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_status_code_range_from_value() {
+        assert_eq!(
+            StatusCodeRange::from_value(&json!(503)),
+            Some(StatusCodeRange {
+                start: 503,
+                end: 503
+            })
+        );
+        assert_eq!(
+            StatusCodeRange::from_value(&json!("500-599")),
+            Some(StatusCodeRange {
+                start: 500,
+                end: 599
+            })
+        );
+        assert_eq!(
+            StatusCodeRange::from_value(&json!("4XX")),
+            Some(StatusCodeRange {
+                start: 400,
+                end: 499
+            })
+        );
+        assert_eq!(StatusCodeRange::from_value(&json!("599-500")), None);
+        assert_eq!(StatusCodeRange::from_value(&json!("oops")), None);
+    }
+
+    #[test]
+    fn test_should_ship_all() {
+        let config = Config::fallback();
+
+        assert!(should_ship(&config, 200, false, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_should_ship_errors_or_slow() {
+        let config = Config {
+            capture_policy: CapturePolicy::ErrorsOrSlow,
+            capture_status_codes: vec![StatusCodeRange {
+                start: 500,
+                end: 599,
+            }],
+            slow_request_threshold_ms: 1000,
+            ..Config::fallback()
+        };
+
+        assert!(should_ship(&config, 502, false, Duration::ZERO));
+        assert!(should_ship(&config, 200, true, Duration::ZERO));
+        assert!(should_ship(
+            &config,
+            200,
+            false,
+            Duration::from_millis(1500)
+        ));
+        assert!(!should_ship(&config, 404, false, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_should_ship_defaults_to_client_and_server_errors() {
+        let config = Config {
+            capture_policy: CapturePolicy::ErrorsOrSlow,
+            ..Config::fallback()
+        };
+
+        assert!(should_ship(&config, 404, false, Duration::ZERO));
+        assert!(!should_ship(&config, 201, false, Duration::from_secs(60)));
+    }
+}
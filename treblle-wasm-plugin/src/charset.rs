@@ -0,0 +1,111 @@
+//! Charset module for the Treblle middleware.
+//!
+//! This module transcodes text bodies to UTF-8 using the `charset` parameter of the
+//! `Content-Type` header, or a byte order mark when no charset is declared.
+
+use std::borrow::Cow;
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::error::{Result, TreblleError};
+
+/// Decodes a text body to UTF-8.
+///
+/// # Arguments
+///
+/// * `body` - The raw body.
+/// * `content_type` - The value of the `Content-Type` header.
+///
+/// # Returns
+///
+/// Returns the decoded text, borrowed when the body is already valid UTF-8, or a
+/// `TreblleError::Charset` if the charset is unknown or the body is not valid in it.
+pub fn decode_text<'a>(body: &'a [u8], content_type: &str) -> Result<Cow<'a, str>> {
+    let encoding = match extract_charset(content_type) {
+        Some(label) => Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| TreblleError::Charset(format!("Unsupported charset: {}", label)))?,
+        None => Encoding::for_bom(body).map_or(UTF_8, |(encoding, _)| encoding),
+    };
+
+    let (text, had_errors) = encoding.decode_with_bom_removal(body);
+
+    if had_errors {
+        return Err(TreblleError::Charset(format!(
+            "Body is not valid {}",
+            encoding.name()
+        )));
+    }
+
+    Ok(text)
+}
+
+/// Extracts the `charset` parameter from a content type.
+pub fn extract_charset(content_type: &str) -> Option<String> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|charset| !charset.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_declared_charset() {
+        let latin1 = b"{\"name\":\"Jos\xe9\"}";
+        let utf16le: Vec<u8> = "{\"a\":1}"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+
+        assert_eq!(
+            decode_text(latin1, "application/json; charset=ISO-8859-1").unwrap(),
+            "{\"name\":\"José\"}"
+        );
+        assert_eq!(
+            decode_text(&utf16le, "application/json; charset=\"utf-16le\"").unwrap(),
+            "{\"a\":1}"
+        );
+    }
+
+    #[test]
+    fn test_decode_bom_and_default() {
+        assert_eq!(
+            decode_text(b"\xef\xbb\xbfhello", "text/plain").unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            decode_text(b"\xff\xfeh\x00i\x00", "text/plain").unwrap(),
+            "hi"
+        );
+        assert!(matches!(
+            decode_text(b"hello", "text/plain").unwrap(),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(
+            decode_text(b"hello", "text/plain; charset=klingon"),
+            Err(TreblleError::Charset(_))
+        ));
+        assert!(matches!(
+            decode_text(b"Jos\xe9", "text/plain"),
+            Err(TreblleError::Charset(_))
+        ));
+    }
+
+    #[test]
+    fn test_extract_charset() {
+        assert_eq!(
+            extract_charset("text/html; Charset=\"UTF-8\""),
+            Some("UTF-8".to_string())
+        );
+        assert_eq!(extract_charset("application/json"), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! Client IP module for the Treblle middleware.
+//!
+//! This module resolves the address of the client that made a request. Forwarding
+//! headers are only believed when they were added by a trusted proxy: the chain is
+//! walked from the connection peer towards the client, and the first address that is
+//! not a trusted proxy is the client.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::config::Config;
+use crate::error::{Result, TreblleError};
+use crate::utils::get_header_value;
+
+/// An IP network in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Parses a network in CIDR notation; a bare address is a single-host network.
+    ///
+    /// # Returns
+    ///
+    /// Returns the network, or a `TreblleError::Config` if it is not valid.
+    pub fn from_str(s: &str) -> Result<Self> {
+        let invalid = || TreblleError::Config(format!("Invalid trusted proxy CIDR: {}", s));
+        let (addr, prefix_len) = match s.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+
+        let network: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse::<u8>().map_err(|_| invalid())?,
+            None => max_len,
+        };
+
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Checks if an address belongs to the network.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(network).into(),
+                u32::from(ip).into(),
+                self.prefix_len,
+                32,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(network.into(), ip.into(), self.prefix_len, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Resolves the client IP of requests.
+#[derive(Clone, Debug, Default)]
+pub struct ClientIpResolver {
+    trusted_proxies: Vec<Cidr>,
+}
+
+impl ClientIpResolver {
+    /// Creates a new `ClientIpResolver` from the `trustedProxies` configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<ClientIpResolver>`, or `TreblleError::Config` if a CIDR is invalid.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(ClientIpResolver {
+            trusted_proxies: config
+                .trusted_proxies
+                .iter()
+                .map(|cidr| Cidr::from_str(cidr))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Resolves the client IP of a request.
+    ///
+    /// When the connection peer is not a trusted proxy it is the client, and forwarding
+    /// headers are ignored since anyone can send them. Otherwise the `Forwarded` header,
+    /// or `X-Forwarded-For` if it is absent, is walked right-to-left skipping trusted
+    /// proxies. `X-Real-IP` is used when a trusted proxy sent neither.
+    ///
+    /// # Arguments
+    ///
+    /// * `source_addr` - The connection peer, as `ip:port`, if the host reported it.
+    /// * `headers` - The request headers.
+    ///
+    /// # Returns
+    ///
+    /// Returns the client IP, or `None` if it cannot be determined.
+    pub fn resolve(
+        &self,
+        source_addr: Option<&str>,
+        headers: &HashMap<String, String>,
+    ) -> Option<String> {
+        let peer = source_addr.and_then(parse_ip);
+
+        if let Some(peer) = peer {
+            if !self.is_trusted(&peer) {
+                return Some(peer.to_string());
+            }
+        }
+
+        let chain = match get_header_value(headers, "Forwarded") {
+            Some(forwarded) => forwarded_for(&forwarded),
+            None => get_header_value(headers, "X-Forwarded-For")
+                .map(|value| value.split(',').map(|hop| hop.trim().to_string()).collect())
+                .unwrap_or_default(),
+        };
+
+        let client = if chain.is_empty() {
+            get_header_value(headers, "X-Real-IP").and_then(|value| parse_ip(&value))
+        } else {
+            self.walk_chain(&chain)
+        };
+
+        client.or(peer).map(|ip| ip.to_string())
+    }
+
+    /// Walks a forwarding chain from the nearest hop, returning the first untrusted address.
+    ///
+    /// A hop that is not an IP address (such as `unknown` or an obfuscated identifier)
+    /// means the client cannot be identified from the chain.
+    fn walk_chain(&self, chain: &[String]) -> Option<IpAddr> {
+        let mut client = None;
+
+        for hop in chain.iter().rev() {
+            let ip = parse_ip(hop)?;
+            client = Some(ip);
+
+            if !self.is_trusted(&ip) {
+                break;
+            }
+        }
+
+        client
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// Extracts the `for` parameter of each element of an RFC 7239 `Forwarded` header.
+fn forwarded_for(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .filter_map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        })
+        .collect()
+}
+
+/// Parses an address that may carry a port, e.g. `192.0.2.1:8080` or `[2001:db8::1]:443`.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest
+            .split(']')
+            .next()?
+            .parse::<Ipv6Addr>()
+            .ok()
+            .map(IpAddr::V6);
+    }
+
+    value.parse().ok().or_else(|| {
+        let (addr, _port) = value.rsplit_once(':')?;
+        addr.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+    })
+}
+
+fn prefix_matches(network: u128, ip: u128, prefix_len: u8, bits: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+
+    let shift = u32::from(bits - prefix_len);
+    network >> shift == ip >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(trusted: &[&str]) -> ClientIpResolver {
+        ClientIpResolver {
+            trusted_proxies: trusted.iter().map(|c| Cidr::from_str(c).unwrap()).collect(),
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let private = Cidr::from_str("10.0.0.0/8").unwrap();
+        let ipv6 = Cidr::from_str("2001:db8::/32").unwrap();
+
+        assert!(private.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(private.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(ipv6.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!ipv6.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(Cidr::from_str("192.0.2.1")
+            .unwrap()
+            .contains(&"192.0.2.1".parse().unwrap()));
+        assert!(Cidr::from_str("0.0.0.0/0")
+            .unwrap()
+            .contains(&"8.8.8.8".parse().unwrap()));
+        assert!(Cidr::from_str("10.0.0.0/33").is_err());
+        assert!(Cidr::from_str("not-an-ip").is_err());
+    }
+
+    #[test]
+    fn test_resolve_ignores_headers_from_untrusted_peer() {
+        let headers = headers(&[("X-Forwarded-For", "1.1.1.1"), ("X-Real-IP", "2.2.2.2")]);
+
+        assert_eq!(
+            resolver(&[]).resolve(Some("203.0.113.7:51234"), &headers),
+            Some("203.0.113.7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_walks_x_forwarded_for() {
+        let resolver = resolver(&["10.0.0.0/8"]);
+        // The client spoofed 6.6.6.6; 198.51.100.1 is what the first trusted proxy saw.
+        let spoofed = headers(&[("X-Forwarded-For", "6.6.6.6, 198.51.100.1, 10.0.0.2")]);
+
+        assert_eq!(
+            resolver.resolve(Some("10.0.0.1:443"), &spoofed),
+            Some("198.51.100.1".to_string())
+        );
+        assert_eq!(
+            resolver.resolve(
+                Some("10.0.0.1:443"),
+                &headers(&[("X-Forwarded-For", "10.0.0.3")])
+            ),
+            Some("10.0.0.3".to_string())
+        );
+        assert_eq!(
+            resolver.resolve(
+                Some("10.0.0.1:443"),
+                &headers(&[("X-Real-IP", "198.51.100.9")])
+            ),
+            Some("198.51.100.9".to_string())
+        );
+        assert_eq!(
+            resolver.resolve(Some("10.0.0.1:443"), &HashMap::new()),
+            Some("10.0.0.1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_forwarded_header() {
+        let resolver = resolver(&["10.0.0.0/8", "2001:db8::/32"]);
+        let headers = headers(&[
+            (
+                "Forwarded",
+                "for=192.0.2.60;proto=http, for=\"[2001:db8::17]:4711\";by=10.0.0.1",
+            ),
+            ("X-Forwarded-For", "6.6.6.6"),
+        ]);
+
+        assert_eq!(
+            resolver.resolve(Some("[2001:db8::1]:80"), &headers),
+            Some("192.0.2.60".to_string())
+        );
+        assert_eq!(
+            forwarded_for("for=unknown, For=\"_hidden\""),
+            vec!["unknown", "_hidden"]
+        );
+        assert_eq!(resolver.resolve(None, &HashMap::new()), None);
+    }
+}
@@ -2,12 +2,24 @@
 //!
 //! This module handles parsing and management of the middleware configuration.
 
+use std::collections::HashMap;
+
 use serde::Deserialize;
 use serde_json::Value;
 
+use crate::body_parser::BodyKind;
+use crate::capture_policy::{CapturePolicy, StatusCodeRange};
 use crate::logger::{log, LogLevel};
-use crate::constants::{DEFAULT_SENSITIVE_KEYS_REGEX, DEFAULT_TREBLLE_API_URLS};
+use crate::constants::{
+    DEFAULT_CAPTURE_CONTENT_TYPES, DEFAULT_MAX_BODY_BYTES, DEFAULT_MAX_DECOMPRESSED_BYTES,
+    DEFAULT_NESTED_JSON_MAX_DEPTH, DEFAULT_REQUEST_ID_HEADER, DEFAULT_SENSITIVE_KEYS_REGEX,
+    DEFAULT_TREBLLE_API_URLS,
+};
 use crate::error::{Result, TreblleError};
+use crate::masking::MaskingAuditMode;
+use crate::route_filter::{RoutePrecedence, RouteRuleConfig};
+use crate::sampling::RouteSampleRate;
+use crate::tags::HeaderTag;
 
 #[cfg(feature = "wasm")]
 use crate::host_functions::host_get_config;
@@ -18,9 +30,49 @@ pub struct Config {
     pub treblle_api_urls: Vec<String>,
     pub api_key: String,
     pub project_id: String,
-    pub route_blacklist: Vec<String>,
+    pub route_blacklist: Vec<RouteRuleConfig>,
+    pub route_whitelist: Vec<RouteRuleConfig>,
+    pub invalid_route_rules: bool,
+    pub route_precedence: RoutePrecedence,
     pub sensitive_keys_regex: String,
+    pub header_allowlist: Vec<String>,
+    pub trusted_proxies: Vec<String>,
+    pub capture_content_types: Vec<BodyKind>,
+    pub max_decompressed_bytes: usize,
+    pub max_body_bytes: usize,
+    pub graphql_operation_as_route: bool,
+    pub route_templates: Vec<String>,
+    pub normalize_routes: bool,
+    pub split_jsonrpc_batches: bool,
+    pub mask_nested_json: bool,
+    pub nested_json_max_depth: usize,
+    pub masking_audit: MaskingAuditMode,
+    pub max_string_length: usize,
+    pub max_array_length: usize,
+    pub max_object_depth: usize,
+    pub max_payload_bytes: usize,
+    pub sample_rate: f64,
+    pub route_sample_rates: Vec<RouteSampleRate>,
+    pub max_events_per_second: u32,
+    pub always_capture_errors: bool,
+    pub capture_policy: CapturePolicy,
+    pub capture_status_codes: Vec<StatusCodeRange>,
+    pub slow_request_threshold_ms: u64,
+    pub server_ip: Option<String>,
+    pub server_name: Option<String>,
+    pub server_os: Option<String>,
+    pub environment: Option<String>,
+    pub region: Option<String>,
+    pub propagate_trace_context: bool,
+    pub accept_b3_headers: bool,
+    pub request_id_header: String,
+    pub static_tags: HashMap<String, String>,
+    pub tags_from_headers: Vec<HeaderTag>,
+    pub tags_from_route: Vec<String>,
+    pub user_id_header: Option<String>,
+    pub user_id_claim: Option<String>,
     pub buffer_response: bool,
+    pub unbuffered_routes: Vec<RouteRuleConfig>,
     pub log_level: LogLevel,
     pub root_ca_path: Option<String>,
 }
@@ -83,7 +135,15 @@ impl Config {
     }
 
     /// Constructs a Config instance from a serde_json::Value.
+    ///
+    /// Malformed route rules are logged and dropped one by one, so they never discard the
+    /// rest of the configuration.
     fn from_value(value: Value) -> Self {
+        let (route_blacklist, invalid_blacklist) =
+            list(&value, "routeBlacklist", RouteRuleConfig::from_value);
+        let (route_whitelist, invalid_whitelist) =
+            list(&value, "routeWhitelist", RouteRuleConfig::from_value);
+
         Config {
             treblle_api_urls: value
                 .get("treblleApiUrls")
@@ -112,8 +172,36 @@ impl Config {
                 .map(String::from)
                 .unwrap_or_default(),
 
-            route_blacklist: value
-                .get("routeBlacklist")
+            route_blacklist,
+
+            route_whitelist,
+
+            invalid_route_rules: invalid_blacklist || invalid_whitelist,
+
+            route_precedence: value
+                .get("routePrecedence")
+                .and_then(|v| v.as_str())
+                .map(RoutePrecedence::from_str)
+                .unwrap_or_default(),
+
+            sensitive_keys_regex: value
+                .get("sensitiveKeysRegex")
+                .and_then(|v| v.as_str())
+                .map(String::from)
+                .unwrap_or_else(|| DEFAULT_SENSITIVE_KEYS_REGEX.to_string()),
+
+            header_allowlist: value
+                .get("headerAllowlist")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+
+            trusted_proxies: value
+                .get("trustedProxies")
                 .and_then(|v| v.as_array())
                 .map(|a| {
                     a.iter()
@@ -122,11 +210,186 @@ impl Config {
                 })
                 .unwrap_or_default(),
 
-            sensitive_keys_regex: value
-                .get("sensitiveKeysRegex")
+            capture_content_types: value
+                .get("captureContentTypes")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().and_then(BodyKind::from_str))
+                        .collect()
+                })
+                .unwrap_or_else(|| DEFAULT_CAPTURE_CONTENT_TYPES.to_vec()),
+
+            max_decompressed_bytes: integer(&value, "maxDecompressedBytes")
+                .map(|v| v as usize)
+                .unwrap_or(DEFAULT_MAX_DECOMPRESSED_BYTES),
+
+            max_body_bytes: integer(&value, "maxBodyBytes")
+                .map(|v| v as usize)
+                .unwrap_or(DEFAULT_MAX_BODY_BYTES),
+
+            graphql_operation_as_route: value
+                .get("graphqlOperationAsRoute")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(false),
+
+            route_templates: value
+                .get("routeTemplates")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(|s| s.trim().to_string()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+
+            normalize_routes: value
+                .get("normalizeRoutes")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(true),
+
+            split_jsonrpc_batches: value
+                .get("splitJsonRpcBatches")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(false),
+
+            mask_nested_json: value
+                .get("maskNestedJson")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(false),
+
+            nested_json_max_depth: integer(&value, "nestedJsonMaxDepth")
+                .map(|v| v as usize)
+                .unwrap_or(DEFAULT_NESTED_JSON_MAX_DEPTH),
+
+            masking_audit: value
+                .get("maskingAudit")
                 .and_then(|v| v.as_str())
-                .map(String::from)
-                .unwrap_or_else(|| DEFAULT_SENSITIVE_KEYS_REGEX.to_string()),
+                .map(MaskingAuditMode::from_str)
+                .unwrap_or_default(),
+
+            max_string_length: integer(&value, "maxStringLength")
+                .map(|v| v as usize)
+                .unwrap_or_default(),
+
+            max_array_length: integer(&value, "maxArrayLength")
+                .map(|v| v as usize)
+                .unwrap_or_default(),
+
+            max_object_depth: integer(&value, "maxObjectDepth")
+                .map(|v| v as usize)
+                .unwrap_or_default(),
+
+            max_payload_bytes: integer(&value, "maxPayloadBytes")
+                .map(|v| v as usize)
+                .unwrap_or_default(),
+
+            sample_rate: value
+                .get("sampleRate")
+                .and_then(|v| {
+                    v.as_f64()
+                        .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                })
+                .unwrap_or(1.0),
+
+            route_sample_rates: list(&value, "routeSampleRates", RouteSampleRate::from_value).0,
+
+            max_events_per_second: integer(&value, "maxEventsPerSecond")
+                .map(|v| v.min(u64::from(u32::MAX)) as u32)
+                .unwrap_or_default(),
+
+            always_capture_errors: value
+                .get("alwaysCaptureErrors")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(false),
+
+            capture_policy: value
+                .get("capturePolicy")
+                .and_then(|v| v.as_str())
+                .map(CapturePolicy::from_str)
+                .unwrap_or_default(),
+
+            capture_status_codes: value
+                .get("captureStatusCodes")
+                .and_then(|v| v.as_array())
+                .map(|a| a.iter().filter_map(StatusCodeRange::from_value).collect())
+                .unwrap_or_default(),
+
+            slow_request_threshold_ms: integer(&value, "slowRequestThresholdMs")
+                .unwrap_or_default(),
+
+            server_ip: optional_string(&value, "serverIp"),
+            server_name: optional_string(&value, "serverName"),
+
+            server_os: optional_string(&value, "serverOs"),
+            environment: optional_string(&value, "environment"),
+            region: optional_string(&value, "region"),
+
+            propagate_trace_context: value
+                .get("propagateTraceContext")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(true),
+
+            accept_b3_headers: value
+                .get("acceptB3Headers")
+                .and_then(|v| {
+                    v.as_bool()
+                        .or_else(|| v.as_str().map(|s| s.to_lowercase() == "true"))
+                })
+                .unwrap_or(false),
+
+            request_id_header: value
+                .get("requestIdHeader")
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|| DEFAULT_REQUEST_ID_HEADER.to_string()),
+
+            static_tags: value
+                .get("staticTags")
+                .and_then(|v| v.as_object())
+                .map(|m| {
+                    m.iter()
+                        .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+
+            tags_from_headers: value
+                .get("tagsFromHeaders")
+                .map(HeaderTag::from_value)
+                .unwrap_or_default(),
+
+            tags_from_route: value
+                .get("tagsFromRoute")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+
+            user_id_header: optional_string(&value, "userIdHeader"),
+
+            user_id_claim: optional_string(&value, "userIdClaim"),
 
             buffer_response: value
                 .get("bufferResponse")
@@ -136,6 +399,8 @@ impl Config {
                 })
                 .unwrap_or(false),
 
+            unbuffered_routes: list(&value, "unbufferedRoutes", RouteRuleConfig::from_value).0,
+
             log_level: value
                 .get("logLevel")
                 .and_then(|v| v.as_str())
@@ -150,7 +415,7 @@ impl Config {
     }
 
     /// Returns a default configuration.
-    fn fallback() -> Self {
+    pub(crate) fn fallback() -> Self {
         Config {
             treblle_api_urls: DEFAULT_TREBLLE_API_URLS
                 .iter()
@@ -159,8 +424,48 @@ impl Config {
             api_key: String::new(),
             project_id: String::new(),
             route_blacklist: Vec::new(),
+            route_whitelist: Vec::new(),
+            invalid_route_rules: false,
+            route_precedence: RoutePrecedence::Blacklist,
             sensitive_keys_regex: DEFAULT_SENSITIVE_KEYS_REGEX.to_string(),
+            header_allowlist: Vec::new(),
+            trusted_proxies: Vec::new(),
+            capture_content_types: DEFAULT_CAPTURE_CONTENT_TYPES.to_vec(),
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            graphql_operation_as_route: false,
+            route_templates: Vec::new(),
+            normalize_routes: true,
+            split_jsonrpc_batches: false,
+            mask_nested_json: false,
+            nested_json_max_depth: DEFAULT_NESTED_JSON_MAX_DEPTH,
+            masking_audit: MaskingAuditMode::None,
+            max_string_length: 0,
+            max_array_length: 0,
+            max_object_depth: 0,
+            max_payload_bytes: 0,
+            sample_rate: 1.0,
+            route_sample_rates: Vec::new(),
+            max_events_per_second: 0,
+            always_capture_errors: false,
+            capture_policy: CapturePolicy::All,
+            capture_status_codes: Vec::new(),
+            slow_request_threshold_ms: 0,
+            server_ip: None,
+            server_name: None,
+            server_os: None,
+            environment: None,
+            region: None,
+            propagate_trace_context: true,
+            accept_b3_headers: false,
+            request_id_header: DEFAULT_REQUEST_ID_HEADER.to_string(),
+            static_tags: HashMap::new(),
+            tags_from_headers: Vec::new(),
+            tags_from_route: Vec::new(),
+            user_id_header: None,
+            user_id_claim: None,
             buffer_response: false,
+            unbuffered_routes: Vec::new(),
             log_level: LogLevel::None,
             root_ca_path: None,
         }
@@ -176,13 +481,78 @@ impl Config {
             return Err(TreblleError::Config("Project ID is required".to_string()));
         }
 
+        // Invalid route rules, sample rates, proxies, tags and so on only disable their own
+        // feature: each component logs the error and falls back to its default when built.
         Ok(())
     }
 }
 
+/// Reads a list setting, logging and dropping each invalid entry.
+///
+/// # Returns
+///
+/// Returns the valid entries, and whether any entry was dropped.
+fn list<T>(value: &Value, key: &str, parse: impl Fn(&Value) -> Result<T>) -> (Vec<T>, bool) {
+    let mut invalid = false;
+    let entries = value
+        .get(key)
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|entry| {
+                    parse(entry)
+                        .map_err(|e| {
+                            log(LogLevel::Error, &format!("Ignoring {} entry: {}", key, e));
+                            invalid = true;
+                        })
+                        .ok()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    (entries, invalid)
+}
+
+/// Reads a non-negative integer setting, given as a number or a numeric string.
+///
+/// Other values are logged and ignored, so the setting keeps its default.
+fn integer(value: &Value, key: &str) -> Option<u64> {
+    let setting = value.get(key).filter(|v| !v.is_null())?;
+    let parsed = match setting {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+
+    if parsed.is_none() {
+        log(
+            LogLevel::Error,
+            &format!(
+                "Ignoring {}: {} is not a non-negative integer",
+                key, setting
+            ),
+        );
+    }
+
+    parsed
+}
+
+/// Reads a non-empty string setting.
+fn optional_string(value: &Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client_ip::ClientIpResolver;
+    use crate::route_filter::RouteFilter;
+    use crate::sampling::Sampler;
     use serde_json::json;
 
     #[test]
@@ -191,9 +561,41 @@ mod tests {
             "treblleApiUrls": ["https://api1.treblle.com", "https://api2.treblle.com"],
             "apiKey": "test_api_key",
             "projectId": "test_project_id",
-            "routeBlacklist": ["/health", "/metrics"],
+            "routeBlacklist": ["/health", { "method": "GET", "path": "/metrics/**" }],
+            "routeWhitelist": [{ "host": "api.example.com" }],
+            "routePrecedence": "whitelist",
             "sensitiveKeysRegex": "password|secret",
+            "headerAllowlist": ["Content-Type", "Authorization"],
+            "trustedProxies": ["10.0.0.0/8", "2001:db8::/32"],
+            "captureContentTypes": ["json", "xml", "text", "unknown"],
+            "maxDecompressedBytes": 1048576,
+            "maxBodyBytes": 65536,
+            "graphqlOperationAsRoute": true,
+            "splitJsonRpcBatches": true,
+            "maskNestedJson": true,
+            "nestedJsonMaxDepth": 5,
+            "maskingAudit": "payload",
+            "maxStringLength": 1024,
+            "maxArrayLength": 100,
+            "maxObjectDepth": 10,
+            "maxPayloadBytes": 2097152,
+            "sampleRate": 0.25,
+            "routeSampleRates": [{ "path": "/orders/**", "rate": 1.0 }],
+            "maxEventsPerSecond": 50,
+            "alwaysCaptureErrors": true,
+            "capturePolicy": "errors_or_slow",
+            "captureStatusCodes": [429, "5xx"],
+            "slowRequestThresholdMs": 2000,
+            "serverIp": "10.1.2.3",
+            "serverName": "edge-fra-1",
+            "serverOs": "linux",
+            "environment": "production",
+            "region": "eu-central-1",
+            "propagateTraceContext": false,
+            "acceptB3Headers": "true",
+            "requestIdHeader": "X-Correlation-Id",
             "bufferResponse": true,
+            "unbufferedRoutes": [{ "path": "/events/**" }],
             "logLevel": "warn",
             "rootCaPath": "/etc/certs/rootCA.pem"
         });
@@ -206,13 +608,187 @@ mod tests {
         );
         assert_eq!(config.api_key, "test_api_key");
         assert_eq!(config.project_id, "test_project_id");
-        assert_eq!(config.route_blacklist, vec!["/health", "/metrics"]);
+        assert_eq!(config.route_blacklist.len(), 2);
+        assert_eq!(config.route_blacklist[0].uri, Some("/health".to_string()));
+        assert_eq!(config.route_blacklist[1].methods, vec!["GET"]);
+        assert_eq!(
+            config.route_blacklist[1].path,
+            Some("/metrics/**".to_string())
+        );
+        assert_eq!(
+            config.route_whitelist[0].host,
+            Some("api.example.com".to_string())
+        );
+        assert_eq!(config.route_precedence, RoutePrecedence::Whitelist);
         assert_eq!(config.sensitive_keys_regex, "password|secret");
+        assert_eq!(
+            config.header_allowlist,
+            vec!["Content-Type", "Authorization"]
+        );
+        assert_eq!(config.trusted_proxies, vec!["10.0.0.0/8", "2001:db8::/32"]);
+        assert_eq!(
+            config.capture_content_types,
+            vec![BodyKind::Json, BodyKind::Xml, BodyKind::Text]
+        );
+        assert_eq!(config.max_decompressed_bytes, 1048576);
+        assert_eq!(config.max_body_bytes, 65536);
+        assert!(config.graphql_operation_as_route);
+        assert!(config.split_jsonrpc_batches);
+        assert!(config.mask_nested_json);
+        assert_eq!(config.nested_json_max_depth, 5);
+        assert_eq!(config.masking_audit, MaskingAuditMode::Payload);
+        assert_eq!(config.max_string_length, 1024);
+        assert_eq!(config.max_array_length, 100);
+        assert_eq!(config.max_object_depth, 10);
+        assert_eq!(config.max_payload_bytes, 2097152);
+        assert_eq!(config.sample_rate, 0.25);
+        assert_eq!(config.route_sample_rates.len(), 1);
+        assert_eq!(config.route_sample_rates[0].rate, 1.0);
+        assert_eq!(
+            config.route_sample_rates[0].rule.path,
+            Some("/orders/**".to_string())
+        );
+        assert_eq!(config.max_events_per_second, 50);
+        assert!(config.always_capture_errors);
+        assert_eq!(config.capture_policy, CapturePolicy::ErrorsOrSlow);
+        assert_eq!(
+            config.capture_status_codes,
+            vec![
+                StatusCodeRange {
+                    start: 429,
+                    end: 429
+                },
+                StatusCodeRange {
+                    start: 500,
+                    end: 599
+                },
+            ]
+        );
+        assert_eq!(config.slow_request_threshold_ms, 2000);
+        assert_eq!(config.server_ip, Some("10.1.2.3".to_string()));
+        assert_eq!(config.server_name, Some("edge-fra-1".to_string()));
+        assert_eq!(config.server_os, Some("linux".to_string()));
+        assert_eq!(config.environment, Some("production".to_string()));
+        assert_eq!(config.region, Some("eu-central-1".to_string()));
+        assert!(!config.propagate_trace_context);
+        assert!(config.accept_b3_headers);
+        assert_eq!(config.request_id_header, "X-Correlation-Id");
         assert!(config.buffer_response);
+        assert_eq!(
+            config.unbuffered_routes[0].path,
+            Some("/events/**".to_string())
+        );
         assert!(matches!(config.log_level, LogLevel::Warn));
         assert_eq!(config.root_ca_path, Some("/etc/certs/rootCA.pem".to_string()));
     }
 
+    #[test]
+    fn test_config_invalid_route_rules() {
+        let config = Config::from_value(json!({
+            "apiKey": "test_api_key",
+            "projectId": "test_project_id",
+            "routeBlacklist": [{ "paht": "/x" }, { "path": "/health" }],
+            "unbufferedRoutes": [{}, { "path": "/events" }],
+            "routeSampleRates": [{ "path": "/orders/**", "rate": "high" }]
+        }));
+
+        assert_eq!(config.api_key, "test_api_key");
+        assert_eq!(config.project_id, "test_project_id");
+        assert_eq!(
+            config.route_blacklist,
+            vec![RouteRuleConfig {
+                path: Some("/health".to_string()),
+                ..Default::default()
+            }]
+        );
+        assert!(config.invalid_route_rules);
+        assert_eq!(config.unbuffered_routes.len(), 1);
+        assert!(config.route_sample_rates.is_empty());
+        assert!(RouteFilter::from_config(&config).is_err());
+
+        let config = Config::from_value(json!({ "routeBlacklist": ["/health"] }));
+        assert!(!config.invalid_route_rules);
+    }
+
+    #[test]
+    fn test_config_integer_settings() {
+        let config = Config::from_value(json!({
+            "maxStringLength": "1000",
+            "maxPayloadBytes": " 65536 ",
+            "maxEventsPerSecond": "50",
+            "slowRequestThresholdMs": "250",
+            "nestedJsonMaxDepth": 5,
+            "maxArrayLength": "many",
+            "maxDecompressedBytes": -1,
+            "maxObjectDepth": 2.5
+        }));
+
+        assert_eq!(config.max_string_length, 1000);
+        assert_eq!(config.max_payload_bytes, 65536);
+        assert_eq!(config.max_events_per_second, 50);
+        assert_eq!(config.slow_request_threshold_ms, 250);
+        assert_eq!(config.nested_json_max_depth, 5);
+        assert_eq!(config.max_array_length, 0);
+        assert_eq!(
+            config.max_decompressed_bytes,
+            DEFAULT_MAX_DECOMPRESSED_BYTES
+        );
+        assert_eq!(config.max_object_depth, 0);
+    }
+
+    #[test]
+    fn test_config_tags_from_value() {
+        let value = json!({
+            "staticTags": { "team": "payments", "ignored": 1 },
+            "tagsFromHeaders": { "X-Tenant-Id": "tenant" },
+            "tagsFromRoute": ["^/services/(?P<service>[^/]+)"]
+        });
+
+        let config = Config::from_value(value);
+
+        assert_eq!(
+            config.static_tags,
+            HashMap::from([("team".to_string(), "payments".to_string())])
+        );
+        assert_eq!(
+            config.tags_from_headers,
+            vec![HeaderTag {
+                header: "X-Tenant-Id".to_string(),
+                tag: "tenant".to_string(),
+            }]
+        );
+        assert_eq!(
+            config.tags_from_route,
+            vec!["^/services/(?P<service>[^/]+)"]
+        );
+    }
+
+    #[test]
+    fn test_config_user_id_from_value() {
+        let value = json!({
+            "userIdHeader": "X-Customer-Id",
+            "userIdClaim": " "
+        });
+
+        let config = Config::from_value(value);
+
+        assert_eq!(config.user_id_header, Some("X-Customer-Id".to_string()));
+        assert_eq!(config.user_id_claim, None);
+    }
+
+    #[test]
+    fn test_config_route_templates_from_value() {
+        let value = json!({
+            "routeTemplates": ["/orders/{id}/items/{itemId}"],
+            "normalizeRoutes": "false"
+        });
+
+        let config = Config::from_value(value);
+
+        assert_eq!(config.route_templates, vec!["/orders/{id}/items/{itemId}"]);
+        assert!(!config.normalize_routes);
+    }
+
     #[test]
     fn test_config_fallback() {
         let config = Config::fallback();
@@ -228,7 +804,43 @@ mod tests {
         assert!(config.project_id.is_empty());
         assert!(config.route_blacklist.is_empty());
         assert_eq!(config.sensitive_keys_regex, DEFAULT_SENSITIVE_KEYS_REGEX);
+        assert!(config.header_allowlist.is_empty());
+        assert_eq!(
+            config.capture_content_types,
+            vec![BodyKind::Json, BodyKind::Form]
+        );
+        assert_eq!(
+            config.max_decompressed_bytes,
+            DEFAULT_MAX_DECOMPRESSED_BYTES
+        );
+        assert_eq!(config.max_body_bytes, DEFAULT_MAX_BODY_BYTES);
+        assert!(!config.graphql_operation_as_route);
+        assert!(config.route_templates.is_empty());
+        assert!(config.normalize_routes);
+        assert!(!config.split_jsonrpc_batches);
+        assert!(!config.mask_nested_json);
+        assert_eq!(config.nested_json_max_depth, DEFAULT_NESTED_JSON_MAX_DEPTH);
+        assert_eq!(config.masking_audit, MaskingAuditMode::None);
+        assert_eq!(config.max_payload_bytes, 0);
+        assert_eq!(config.sample_rate, 1.0);
+        assert_eq!(config.max_events_per_second, 0);
+        assert!(!config.always_capture_errors);
+        assert_eq!(config.capture_policy, CapturePolicy::All);
+        assert!(config.capture_status_codes.is_empty());
+        assert_eq!(config.slow_request_threshold_ms, 0);
+        assert_eq!(config.server_ip, None);
+        assert_eq!(config.environment, None);
+        assert!(config.propagate_trace_context);
+        assert!(!config.accept_b3_headers);
+        assert_eq!(config.request_id_header, "X-Request-Id");
+        assert!(config.static_tags.is_empty());
+        assert!(config.tags_from_headers.is_empty());
+        assert!(config.tags_from_route.is_empty());
+        assert_eq!(config.user_id_header, None);
+        assert_eq!(config.user_id_claim, None);
         assert!(!config.buffer_response);
+        assert!(config.unbuffered_routes.is_empty());
+        assert!(config.trusted_proxies.is_empty());
         assert!(matches!(config.log_level, LogLevel::None));
         assert_eq!(config.root_ca_path, None);
     }
@@ -244,6 +856,7 @@ mod tests {
             buffer_response: false,
             log_level: Default::default(),
             root_ca_path: None,
+            ..Config::fallback()
         };
 
         assert!(valid_config.validate().is_ok());
@@ -257,9 +870,38 @@ mod tests {
             buffer_response: false,
             log_level: Default::default(),
             root_ca_path: None,
+            ..Config::fallback()
         };
 
         assert!(invalid_config.validate().is_err());
+
+        // Sub-settings are checked by their components, which degrade on their own
+        // instead of discarding the whole configuration.
+        let invalid_settings_config = Config {
+            api_key: "valid_key".to_string(),
+            project_id: "valid_id".to_string(),
+            route_blacklist: vec![RouteRuleConfig {
+                uri: Some("[invalid regex".to_string()),
+                ..Default::default()
+            }],
+            sample_rate: 2.0,
+            trusted_proxies: vec!["10.0.0.0/40".to_string()],
+            ..Config::fallback()
+        };
+
+        assert!(invalid_settings_config.validate().is_ok());
+        assert!(matches!(
+            RouteFilter::from_config(&invalid_settings_config),
+            Err(TreblleError::Config(_))
+        ));
+        assert!(matches!(
+            Sampler::from_config(&invalid_settings_config),
+            Err(TreblleError::Config(_))
+        ));
+        assert!(matches!(
+            ClientIpResolver::from_config(&invalid_settings_config),
+            Err(TreblleError::Config(_))
+        ));
     }
 
     #[test]
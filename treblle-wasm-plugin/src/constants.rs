@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use crate::body_parser::BodyKind;
+
 pub const LOG_LEVEL_DEBUG: i32 = -1;
 pub const LOG_LEVEL_INFO: i32 = 0;
 pub const LOG_LEVEL_WARN: i32 = 1;
@@ -19,4 +21,62 @@ pub const DEFAULT_TREBLLE_API_URLS: [&str; 3] = [
 pub const DEFAULT_SENSITIVE_KEYS_REGEX: &str =
     r"(?i)(password|pwd|secret|password_confirmation|cc|card_number|ccv|ssn|credit_score)";
 
+pub const DEFAULT_NESTED_JSON_MAX_DEPTH: usize = 3;
+
+/// Deepest XML element converted to JSON, matching the recursion limit of JSON bodies so
+/// masking and shaping never recurse further.
+pub const MAX_XML_DEPTH: usize = 128;
+
+/// Replaces XML elements nested deeper than `MAX_XML_DEPTH`.
+pub const XML_DEPTH_EXCEEDED: &str = "[element: max depth exceeded]";
+
+/// Default upper bound for a decompressed body, to defeat decompression bombs.
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 10 * 1024 * 1024;
+
+/// Default largest body kept for parsing; larger bodies are only counted.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Body kinds captured when `captureContentTypes` is not set.
+pub const DEFAULT_CAPTURE_CONTENT_TYPES: [BodyKind; 2] = [BodyKind::Json, BodyKind::Form];
+
+/// Headers that always carry credentials and are masked regardless of `sensitiveKeysRegex`.
+pub const CREDENTIAL_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// Attributes of a `Set-Cookie` header; anything else after the first pair is masked.
+pub const SET_COOKIE_ATTRIBUTES: [&str; 8] = [
+    "Path",
+    "Domain",
+    "Expires",
+    "Max-Age",
+    "Secure",
+    "HttpOnly",
+    "SameSite",
+    "Partitioned",
+];
+
+/// Separator the host places between the values of a repeated header.
+pub const HEADER_VALUE_SEPARATOR: char = '\0';
+
+/// Header carrying the request ID when `requestIdHeader` is not set.
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Environment variables read, in order, when `serverIp` is not configured.
+pub const SERVER_IP_ENV_VARS: [&str; 3] = ["SERVER_IP", "POD_IP", "HOST_IP"];
+/// Environment variables read, in order, when `serverName` is not configured.
+pub const SERVER_NAME_ENV_VARS: [&str; 3] = ["SERVER_NAME", "NODE_NAME", "HOSTNAME"];
+/// Environment variables read, in order, when `serverOs` is not configured.
+pub const SERVER_OS_ENV_VARS: [&str; 2] = ["SERVER_OS", "OSTYPE"];
+/// Environment variables read, in order, when `environment` is not configured.
+pub const ENVIRONMENT_ENV_VARS: [&str; 2] = ["TREBLLE_ENVIRONMENT", "ENVIRONMENT"];
+/// Environment variables read, in order, when `region` is not configured.
+pub const REGION_ENV_VARS: [&str; 3] = ["REGION", "AWS_REGION", "FLY_REGION"];
+/// Environment variable holding the Traefik version, reported in `server.software`.
+pub const TRAEFIK_VERSION_ENV_VAR: &str = "TRAEFIK_VERSION";
+
 pub const HTTP_TIMEOUT_SECONDS: u64 = 10;
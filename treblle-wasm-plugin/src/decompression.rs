@@ -0,0 +1,151 @@
+//! Decompression module for the Treblle middleware.
+//!
+//! This module decodes `Content-Encoding` compressed bodies so they can be parsed and
+//! masked. Decompression is for inspection only: the original bytes are what gets
+//! written back to Traefik.
+
+use std::borrow::Cow;
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use crate::error::{Result, TreblleError};
+
+/// Size of the buffer used by the brotli decoder.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Decodes a body according to its `Content-Encoding` header.
+///
+/// Encodings are undone in reverse order of application, so `gzip, br` is decoded
+/// as brotli first, then gzip.
+///
+/// # Arguments
+///
+/// * `body` - The raw body, as received on the wire.
+/// * `content_encoding` - The value of the `Content-Encoding` header.
+/// * `max_bytes` - The maximum decompressed size; `0` disables the limit.
+///
+/// # Returns
+///
+/// Returns the decoded body, borrowed if no decoding was needed, or a
+/// `TreblleError::Decompression` if the encoding is unsupported, the data is corrupt or
+/// the decompressed body exceeds `max_bytes`.
+pub fn decode_body<'a>(
+    body: &'a [u8],
+    content_encoding: &str,
+    max_bytes: usize,
+) -> Result<Cow<'a, [u8]>> {
+    let mut decoded = Cow::Borrowed(body);
+
+    if body.is_empty() {
+        return Ok(decoded);
+    }
+
+    for encoding in content_encoding.split(',').rev() {
+        let encoding = encoding.trim().to_lowercase();
+
+        decoded = match encoding.as_str() {
+            "" | "identity" => decoded,
+            "gzip" | "x-gzip" => Cow::Owned(read_limited(GzDecoder::new(&decoded[..]), max_bytes)?),
+            "deflate" => Cow::Owned(inflate(&decoded, max_bytes)?),
+            "br" => Cow::Owned(read_limited(
+                brotli_decompressor::Decompressor::new(&decoded[..], BROTLI_BUFFER_SIZE),
+                max_bytes,
+            )?),
+            other => {
+                return Err(TreblleError::Decompression(format!(
+                    "Unsupported content encoding: {}",
+                    other
+                )))
+            }
+        };
+    }
+
+    Ok(decoded)
+}
+
+/// Inflates a `deflate` body.
+///
+/// The HTTP `deflate` coding is zlib-wrapped, but some servers send raw deflate data,
+/// so that is tried when the zlib header is missing.
+fn inflate(body: &[u8], max_bytes: usize) -> Result<Vec<u8>> {
+    read_limited(ZlibDecoder::new(body), max_bytes)
+        .or_else(|_| read_limited(DeflateDecoder::new(body), max_bytes))
+}
+
+/// Reads a decoder to the end, failing once more than `max_bytes` are produced.
+fn read_limited<R: Read>(reader: R, max_bytes: usize) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+
+    // Read one byte past the limit to tell "exactly at the limit" from "over it".
+    let limit = match max_bytes {
+        0 => u64::MAX,
+        max => max as u64 + 1,
+    };
+
+    reader
+        .take(limit)
+        .read_to_end(&mut decoded)
+        .map_err(|e| TreblleError::Decompression(e.to_string()))?;
+
+    if max_bytes > 0 && decoded.len() > max_bytes {
+        return Err(TreblleError::Decompression(format!(
+            "Decompressed body exceeds {} bytes",
+            max_bytes
+        )));
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    const BODY: &[u8] = br#"{"password":"hunter2"}"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode_gzip_and_deflate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(BODY).unwrap();
+        let deflated = encoder.finish().unwrap();
+
+        assert_eq!(&*decode_body(&gzip(BODY), "gzip", 0).unwrap(), BODY);
+        assert_eq!(&*decode_body(&deflated, "deflate", 0).unwrap(), BODY);
+        assert!(matches!(decode_body(BODY, "", 0), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn test_decode_brotli() {
+        // A single uncompressed meta-block holding the 22 body bytes, then an empty last one.
+        let compressed = [&[0x50, 0x01, 0x10][..], BODY, &[0x03]].concat();
+
+        assert_eq!(&*decode_body(&compressed, "br", 0).unwrap(), BODY);
+    }
+
+    #[test]
+    fn test_decode_limit() {
+        let bomb = gzip(&vec![b'a'; 10_000]);
+
+        assert!(matches!(
+            decode_body(&bomb, "gzip", 1_000),
+            Err(TreblleError::Decompression(_))
+        ));
+        assert_eq!(decode_body(&bomb, "gzip", 10_000).unwrap().len(), 10_000);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode_body(BODY, "gzip", 0).is_err());
+        assert!(decode_body(BODY, "compress", 0).is_err());
+    }
+}
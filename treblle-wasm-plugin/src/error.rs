@@ -61,6 +61,22 @@ pub enum TreblleError {
     #[error("Lock acquisition error: {0}")]
     LockError(String),
 
+    /// Represents payloads that exceed the configured size limit even after shaping.
+    #[error("Payload too large: {0} bytes")]
+    PayloadTooLarge(usize),
+
+    /// Represents bodies that cannot be decompressed for inspection.
+    #[error("Decompression error: {0}")]
+    Decompression(String),
+
+    /// Represents bodies whose declared or detected charset cannot be decoded.
+    #[error("Charset error: {0}")]
+    Charset(String),
+
+    /// Represents malformed XML bodies.
+    #[error("XML error: {0}")]
+    Xml(String),
+
 }
 
 
@@ -0,0 +1,278 @@
+//! Error details module for the Treblle middleware.
+//!
+//! This module turns failed responses into `ErrorInfo` entries, reading the actual error
+//! from RFC 7807 problem details and common JSON error shapes in the response body, and
+//! telling errors returned by the upstream service apart from errors raised by Traefik.
+
+use serde_json::Value;
+
+use crate::schema::ErrorInfo;
+
+/// Content type of RFC 7807 problem details.
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Number of `errors[]` entries joined into the message.
+const MAX_ERROR_MESSAGES: usize = 10;
+
+/// Number of characters kept of the message, since it is not part of payload shaping.
+const MAX_ERROR_MESSAGE_LENGTH: usize = 1024;
+
+/// The error read from a response body.
+#[derive(Debug, PartialEq)]
+struct ErrorDetails {
+    error_type: Option<String>,
+    message: String,
+}
+
+/// Builds the `ErrorInfo` of a failed response.
+///
+/// # Arguments
+///
+/// * `status_code` - The HTTP status code of the response.
+/// * `traefik_error` - Whether Traefik reported the error (`is_error`), e.g. because the
+///   upstream service could not be reached, rather than the upstream returning it.
+/// * `body` - The parsed response body, if any.
+/// * `content_type` - The `Content-Type` of the response.
+///
+/// # Returns
+///
+/// Returns the error, with the message and type from the body when it has a known shape,
+/// otherwise `HTTP status code: N`. The message is cut to `MAX_ERROR_MESSAGE_LENGTH`
+/// characters.
+pub fn response_error(
+    status_code: u32,
+    traefik_error: bool,
+    body: Option<&Value>,
+    content_type: &str,
+) -> ErrorInfo {
+    let (source, default_type) = if traefik_error {
+        ("traefik", "Traefik Error")
+    } else {
+        ("response", "HTTP Error")
+    };
+    let details = body.and_then(|body| parse_details(body, content_type));
+
+    ErrorInfo {
+        source: source.to_string(),
+        error_type: details
+            .as_ref()
+            .and_then(|d| d.error_type.clone())
+            .unwrap_or_else(|| default_type.to_string()),
+        message: details
+            .map(|d| truncate(d.message))
+            .unwrap_or_else(|| format!("HTTP status code: {}", status_code)),
+        file: String::new(),
+        line: 0,
+    }
+}
+
+/// Reads the error of a response body, trying problem details before the common shapes.
+fn parse_details(body: &Value, content_type: &str) -> Option<ErrorDetails> {
+    let is_problem = content_type
+        .split(';')
+        .next()
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(PROBLEM_JSON));
+
+    if is_problem || (body.get("title").is_some() && body.get("detail").is_some()) {
+        if let Some(details) = problem_details(body) {
+            return Some(details);
+        }
+    }
+
+    nested_error(body)
+        .or_else(|| error_list(body))
+        .or_else(|| top_level_message(body))
+}
+
+/// Reads RFC 7807 problem details: `{type, title, status, detail, instance}`.
+fn problem_details(body: &Value) -> Option<ErrorDetails> {
+    let title = string(body.get("title"));
+    let problem_type = string(body.get("type")).filter(|t| t != "about:blank");
+
+    Some(ErrorDetails {
+        message: string(body.get("detail")).or_else(|| title.clone())?,
+        error_type: title.or(problem_type),
+    })
+}
+
+/// Reads `{error: {message, code}}` and the OAuth-style `{error, error_description}`.
+fn nested_error(body: &Value) -> Option<ErrorDetails> {
+    match body.get("error")? {
+        error @ Value::Object(_) => Some(ErrorDetails {
+            message: string(error.get("message"))?,
+            error_type: string(error.get("code")).or_else(|| string(error.get("type"))),
+        }),
+        Value::String(code) => Some(ErrorDetails {
+            message: string(body.get("error_description"))
+                .or_else(|| string(body.get("message")))
+                .unwrap_or_else(|| code.clone()),
+            error_type: Some(code.clone()),
+        }),
+        _ => None,
+    }
+}
+
+/// Reads `{errors: [...]}`, whose items are messages or `{message, code}` objects.
+///
+/// Only the first `MAX_ERROR_MESSAGES` messages are joined, followed by how many were left out.
+fn error_list(body: &Value) -> Option<ErrorDetails> {
+    let errors = body.get("errors")?.as_array()?;
+
+    let mut messages = errors.iter().filter_map(|error| match error {
+        Value::String(message) => Some(truncate(message.clone())),
+        _ => string(error.get("message"))
+            .or_else(|| string(error.get("detail")))
+            .map(truncate),
+    });
+    let mut message = messages
+        .by_ref()
+        .take(MAX_ERROR_MESSAGES)
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    if message.is_empty() {
+        return None;
+    }
+
+    let omitted = messages.count();
+    if omitted > 0 {
+        message.push_str(&format!(" (and {} more)", omitted));
+    }
+
+    Some(ErrorDetails {
+        message,
+        error_type: errors
+            .iter()
+            .find_map(|error| string(error.get("code")).or_else(|| string(error.get("type")))),
+    })
+}
+
+/// Reads a top-level `{message, code}`.
+fn top_level_message(body: &Value) -> Option<ErrorDetails> {
+    Some(ErrorDetails {
+        message: string(body.get("message"))?,
+        error_type: string(body.get("code")),
+    })
+}
+
+/// Cuts a message to `MAX_ERROR_MESSAGE_LENGTH` characters.
+fn truncate(message: String) -> String {
+    match message.char_indices().nth(MAX_ERROR_MESSAGE_LENGTH) {
+        Some((end, _)) => format!("{}...", &message[..end]),
+        None => message,
+    }
+}
+
+/// Reads a non-empty string, or a number as a string, since error codes are often numeric.
+fn string(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error(body: Value, content_type: &str) -> (String, String) {
+        let error = response_error(400, false, Some(&body), content_type);
+        (error.error_type, error.message)
+    }
+
+    #[test]
+    fn test_problem_details() {
+        let body = json!({
+            "type": "https://example.com/probs/out-of-credit",
+            "title": "You do not have enough credit.",
+            "status": 403,
+            "detail": "Your current balance is 30, but that costs 50."
+        });
+
+        assert_eq!(
+            error(body, "application/problem+json; charset=utf-8"),
+            (
+                "You do not have enough credit.".to_string(),
+                "Your current balance is 30, but that costs 50.".to_string()
+            )
+        );
+        assert_eq!(
+            error(
+                json!({ "type": "https://example.com/probs/gone", "title": "Gone" }),
+                PROBLEM_JSON
+            ),
+            ("Gone".to_string(), "Gone".to_string())
+        );
+    }
+
+    #[test]
+    fn test_common_error_shapes() {
+        assert_eq!(
+            error(
+                json!({ "error": { "message": "Card declined", "code": "card_declined" } }),
+                "application/json"
+            ),
+            ("card_declined".to_string(), "Card declined".to_string())
+        );
+        assert_eq!(
+            error(
+                json!({ "error": "invalid_grant", "error_description": "Token expired" }),
+                "application/json"
+            ),
+            ("invalid_grant".to_string(), "Token expired".to_string())
+        );
+        assert_eq!(
+            error(
+                json!({ "errors": [{ "message": "name is required", "code": 1001 }, "email is invalid"] }),
+                "application/json"
+            ),
+            (
+                "1001".to_string(),
+                "name is required; email is invalid".to_string()
+            )
+        );
+        assert_eq!(
+            error(json!({ "message": "Not allowed" }), "application/json"),
+            ("HTTP Error".to_string(), "Not allowed".to_string())
+        );
+        assert_eq!(
+            error(json!({ "status": "failed" }), "application/json"),
+            (
+                "HTTP Error".to_string(),
+                "HTTP status code: 400".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_error_message_limits() {
+        let errors: Vec<Value> = (0..50_000)
+            .map(|i| json!({ "message": format!("field {} is invalid", i) }))
+            .collect();
+        let (_, message) = error(json!({ "errors": errors }), "application/json");
+
+        assert!(message.starts_with("field 0 is invalid; field 1 is invalid; "));
+        assert!(message.ends_with("field 9 is invalid (and 49990 more)"));
+
+        let (_, message) = error(json!({ "message": "é".repeat(5000) }), "application/json");
+
+        assert_eq!(message.chars().count(), MAX_ERROR_MESSAGE_LENGTH + 3);
+        assert!(message.ends_with("é..."));
+    }
+
+    #[test]
+    fn test_traefik_error() {
+        let error = response_error(502, true, Some(&json!("Bad Gateway")), "text/plain");
+
+        assert_eq!(error.source, "traefik");
+        assert_eq!(error.error_type, "Traefik Error");
+        assert_eq!(error.message, "HTTP status code: 502");
+
+        let error = response_error(502, false, None, "");
+
+        assert_eq!(error.source, "response");
+        assert_eq!(error.error_type, "HTTP Error");
+    }
+}
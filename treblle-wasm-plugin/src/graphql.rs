@@ -0,0 +1,328 @@
+//! GraphQL module for the Treblle middleware.
+//!
+//! This module recognizes GraphQL requests, extracts the executed operation and masks
+//! sensitive arguments and variables before the request is reported.
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+use crate::masking::{Masker, RULE_SENSITIVE_KEYS};
+use crate::schema::{GraphQlInfo, MaskedField};
+
+const APPLICATION_GRAPHQL: &str = "application/graphql";
+const OPERATION_TYPES: [&str; 3] = ["query", "mutation", "subscription"];
+
+/// Matches inline string arguments such as `password: "secret"` or `password: """secret"""`.
+///
+/// Block strings are tried first and end at the first unescaped `"""`; the regex crate has
+/// no lookahead, so a lazy repetition stands in for `"(?!"")`.
+static STRING_ARGUMENT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"([_A-Za-z][_0-9A-Za-z]*)([ \t\r\n]*:[ \t\r\n]*)(?:"""(?:\\"""|(?s:.))*?"""|"(?:[^"\\]|\\.)*")"#,
+    )
+    .unwrap()
+});
+
+/// Detects a GraphQL request and extracts the operation it executes.
+///
+/// Both `application/graphql` bodies and JSON bodies of the form
+/// `{ "query": ..., "variables": ..., "operationName": ... }` are recognized.
+///
+/// # Arguments
+///
+/// * `body` - The parsed request body.
+/// * `content_type` - The value of the `Content-Type` header.
+///
+/// # Returns
+///
+/// Returns the operation type and name, or `None` if the body is not a GraphQL request.
+pub fn detect_operation(body: &Value, content_type: &str) -> Option<GraphQlInfo> {
+    if is_graphql(content_type) {
+        return parse_operation(body.as_str()?, None);
+    }
+
+    let query = body.get("query")?.as_str()?;
+    let operation_name = body.get("operationName").and_then(|v| v.as_str());
+
+    parse_operation(query, operation_name)
+}
+
+/// Checks if a content type is `application/graphql`.
+pub fn is_graphql(content_type: &str) -> bool {
+    content_type.to_lowercase().contains(APPLICATION_GRAPHQL)
+}
+
+/// Prepares a GraphQL body for masking.
+///
+/// Variables sent as a JSON string are parsed so the regular masking rules reach them,
+/// and string literals passed inline to arguments with sensitive names are masked in the
+/// query text itself.
+///
+/// # Arguments
+///
+/// * `body` - The parsed request body, modified in place.
+/// * `masker` - The masker holding the sensitive keys pattern.
+/// * `root` - The path of the body, such as `request.body`.
+/// * `masked_fields` - Receives the path and rule of each masked argument.
+pub fn prepare_body(
+    body: &mut Value,
+    masker: &Masker,
+    root: &str,
+    masked_fields: &mut Vec<MaskedField>,
+) {
+    match body {
+        Value::String(query) => {
+            *query = mask_query_arguments(query, masker, root, masked_fields);
+        }
+        Value::Object(map) => {
+            if let Some(Value::String(variables)) = map.get("variables") {
+                if let Ok(parsed @ Value::Object(_)) = serde_json::from_str(variables) {
+                    map.insert("variables".to_string(), parsed);
+                }
+            }
+
+            if let Some(Value::String(query)) = map.get_mut("query") {
+                let path = format!("{}.query", root);
+                *query = mask_query_arguments(query, masker, &path, masked_fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Builds the route reported for a GraphQL operation, e.g. `/graphql/GetUser`.
+///
+/// # Returns
+///
+/// Returns `None` for anonymous operations.
+pub fn operation_route(uri: &str, operation: &GraphQlInfo) -> Option<String> {
+    let name = operation.operation_name.as_ref()?;
+    let path = uri.split(['?', '#']).next().unwrap_or_default();
+
+    Some(format!("{}/{}", path.trim_end_matches('/'), name))
+}
+
+/// Finds the operation to execute in a GraphQL document.
+///
+/// When `operation_name` is set the matching operation is returned, otherwise the first one.
+fn parse_operation(query: &str, operation_name: Option<&str>) -> Option<GraphQlInfo> {
+    let operations = operations(query);
+
+    match operation_name.filter(|name| !name.is_empty()) {
+        Some(name) => operations
+            .into_iter()
+            .find(|op| op.operation_name.as_deref() == Some(name)),
+        None => operations.into_iter().next(),
+    }
+}
+
+/// Lists the operations defined at the top level of a GraphQL document.
+fn operations(query: &str) -> Vec<GraphQlInfo> {
+    let mut operations = Vec::new();
+    let mut chars = query.chars().peekable();
+    let mut depth = 0usize;
+    // An operation keyword that has not been followed by a name yet.
+    let mut pending_type: Option<String> = None;
+    // Inside a named definition (operation or fragment) whose selection set is not open yet.
+    let mut in_definition = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            '{' | '(' | '[' => {
+                if depth == 0 {
+                    if let Some(operation_type) = pending_type.take() {
+                        operations.push(GraphQlInfo {
+                            operation_type,
+                            operation_name: None,
+                        });
+                    } else if c == '{' && !in_definition {
+                        // Shorthand `{ ... }` is an anonymous query.
+                        operations.push(GraphQlInfo {
+                            operation_type: "query".to_string(),
+                            operation_name: None,
+                        });
+                    }
+                }
+                depth += 1;
+            }
+            '}' | ')' | ']' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 && c == '}' {
+                    in_definition = false;
+                }
+            }
+            c if depth == 0 && (c.is_ascii_alphabetic() || c == '_' || c == '@') => {
+                let mut word = String::new();
+                if c != '@' {
+                    word.push(c);
+                }
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+
+                if c == '@' {
+                    continue;
+                }
+
+                if let Some(operation_type) = pending_type.take() {
+                    operations.push(GraphQlInfo {
+                        operation_type,
+                        operation_name: Some(word),
+                    });
+                } else if !in_definition && OPERATION_TYPES.contains(&word.as_str()) {
+                    pending_type = Some(word);
+                    in_definition = true;
+                } else if !in_definition && word == "fragment" {
+                    in_definition = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(operation_type) = pending_type {
+        operations.push(GraphQlInfo {
+            operation_type,
+            operation_name: None,
+        });
+    }
+
+    operations
+}
+
+/// Masks inline string arguments whose name matches the sensitive keys pattern.
+fn mask_query_arguments(
+    query: &str,
+    masker: &Masker,
+    path: &str,
+    masked_fields: &mut Vec<MaskedField>,
+) -> String {
+    STRING_ARGUMENT
+        .replace_all(query, |caps: &Captures| {
+            if masker.is_sensitive_key(&caps[1]) {
+                masked_fields.push(MaskedField {
+                    path: format!("{}({})", path, &caps[1]),
+                    rule: RULE_SENSITIVE_KEYS.to_string(),
+                });
+                format!("{}{}\"*****\"", &caps[1], &caps[2])
+            } else {
+                caps[0].to_string()
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn info(operation_type: &str, operation_name: Option<&str>) -> Option<GraphQlInfo> {
+        Some(GraphQlInfo {
+            operation_type: operation_type.to_string(),
+            operation_name: operation_name.map(String::from),
+        })
+    }
+
+    #[test]
+    fn test_detect_operation() {
+        let body = json!({
+            "query": "# fetch\nquery GetUser($id: ID!) { user(id: $id) { name } }\nmutation Logout { logout }",
+            "operationName": "Logout"
+        });
+
+        assert_eq!(
+            detect_operation(&body, "application/json"),
+            info("mutation", Some("Logout"))
+        );
+        assert_eq!(
+            detect_operation(&json!("{ me { name } }"), "application/graphql"),
+            info("query", None)
+        );
+        assert_eq!(
+            detect_operation(
+                &json!({ "query": "fragment F on User { id } subscription OnMessage { message { ...F } }" }),
+                "application/json"
+            ),
+            info("subscription", Some("OnMessage"))
+        );
+        assert_eq!(
+            detect_operation(&json!({ "name": "x" }), "application/json"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prepare_body_masks_variables_and_arguments() {
+        let masker = Masker::new("(?i)password").unwrap();
+        let mut masked_fields = Vec::new();
+        let mut body = json!({
+            "query": "mutation Login { login(user: \"john\", password: \"hunter2\") { token } }",
+            "variables": "{\"password\":\"hunter2\"}"
+        });
+
+        prepare_body(&mut body, &masker, "request.body", &mut masked_fields);
+        let masked = masker.mask_audited(&body, "", &mut Vec::new());
+
+        assert_eq!(
+            masked["query"],
+            "mutation Login { login(user: \"john\", password: \"*****\") { token } }"
+        );
+        assert_eq!(masked["variables"]["password"], "*****");
+        assert_eq!(masked_fields[0].path, "request.body.query(password)");
+    }
+
+    #[test]
+    fn test_prepare_body_masks_block_string_arguments() {
+        let masker = Masker::new("(?i)password").unwrap();
+        let mut body = json!(
+            "mutation { login(password: \"\"\"hunter2 \"quoted\" \\\"\"\" \n\"\"\", note: \"\"\"keep\"\"\") { token } }"
+        );
+
+        prepare_body(&mut body, &masker, "request.body", &mut Vec::new());
+
+        assert_eq!(
+            body,
+            "mutation { login(password: \"*****\", note: \"\"\"keep\"\"\") { token } }"
+        );
+    }
+
+    #[test]
+    fn test_operation_route() {
+        let operation = GraphQlInfo {
+            operation_type: "query".to_string(),
+            operation_name: Some("GetUser".to_string()),
+        };
+
+        assert_eq!(
+            operation_route("/graphql/?debug=1", &operation),
+            Some("/graphql/GetUser".to_string())
+        );
+        assert_eq!(
+            operation_route("/graphql", &info("query", None).unwrap()),
+            None
+        );
+    }
+}
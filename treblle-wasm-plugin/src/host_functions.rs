@@ -7,6 +7,11 @@ use core::str;
 use std::ffi::CString;
 
 use crate::error::{Result, TreblleError};
+use crate::utils::ReadBody;
+
+/// Size of the chunks in which bodies are read from the host.
+#[cfg(feature = "wasm")]
+const READ_BODY_CHUNK_SIZE: usize = 16 * 1024;
 
 // Defines the external functions provided by the host environment.
 // External functions come from the `http_handler` module exposed by `http-wasm-host-go/api/handler`
@@ -22,6 +27,7 @@ extern "C" {
     fn get_method(buf: *mut u8, buf_limit: i32) -> i32;
     fn get_uri(ptr: *mut u8, buf_limit: u32) -> i32;
     fn get_protocol_version(buf: *mut u8, buf_limit: i32) -> i32;
+    fn get_source_addr(buf: *mut u8, buf_limit: i32) -> i32;
     fn get_header_names(header_kind: u32, buf: *mut u8, buf_limit: i32) -> i64;
     fn get_header_values(
         header_kind: u32,
@@ -30,6 +36,13 @@ extern "C" {
         buf: *mut u8,
         buf_limit: i32,
     ) -> i64;
+    fn set_header_value(
+        header_kind: u32,
+        name: *const u8,
+        name_len: u32,
+        value: *const u8,
+        value_len: u32,
+    );
     fn read_body(body_kind: u32, ptr: *mut u8, buf_limit: u32) -> i64;
     fn write_body(body_kind: u32, ptr: *const u8, message_len: u32);
 
@@ -111,6 +124,16 @@ pub fn host_get_protocol_version() -> Result<String> {
     read_from_buffer(|buf, buf_limit| unsafe { get_protocol_version(buf, buf_limit) })
 }
 
+/// Retrieves the address of the peer that opened the connection, as `ip:port`.
+///
+/// # Returns
+///
+/// Returns the source address as a string, or an error if retrieval fails.
+#[cfg(feature = "wasm")]
+pub fn host_get_source_addr() -> Result<String> {
+    read_from_buffer(|buf, buf_limit| unsafe { get_source_addr(buf, buf_limit) })
+}
+
 /// Retrieves the header names for a given header kind.
 ///
 /// # Arguments
@@ -119,7 +142,7 @@ pub fn host_get_protocol_version() -> Result<String> {
 ///
 /// # Returns
 ///
-/// Returns a comma-separated string of header names, or an error if retrieval fails.
+/// Returns the header names, each ending with a NUL byte, or an error if retrieval fails.
 #[cfg(feature = "wasm")]
 pub fn host_get_header_names(header_kind: u32) -> Result<String> {
     read_from_buffer(|buf, buf_limit| unsafe {
@@ -136,7 +159,7 @@ pub fn host_get_header_names(header_kind: u32) -> Result<String> {
 ///
 /// # Returns
 ///
-/// Returns a comma-separated string of header values, or an error if retrieval fails.
+/// Returns the header values, each ending with a NUL byte, or an error if retrieval fails.
 #[cfg(feature = "wasm")]
 pub fn host_get_header_values(header_kind: u32, name: &str) -> Result<String> {
     let sanitized_name = name.replace('\0', "");
@@ -154,27 +177,80 @@ pub fn host_get_header_values(header_kind: u32, name: &str) -> Result<String> {
     })
 }
 
-/// Reads the body of the current request or response.
+/// Sets a header, replacing any existing values.
+///
+/// # Arguments
+///
+/// * `header_kind` - The kind of headers to modify (0 for request, 1 for response).
+/// * `name` - The name of the header.
+/// * `value` - The value to set.
+///
+/// # Returns
+///
+/// Returns `Ok(())`, or an error if the name or value contains a NUL byte.
+#[cfg(feature = "wasm")]
+pub fn host_set_header_value(header_kind: u32, name: &str, value: &str) -> Result<()> {
+    if name.contains('\0') || value.contains('\0') {
+        return Err(TreblleError::HostFunction(format!(
+            "Invalid header {}: contains a NUL byte",
+            name
+        )));
+    }
+
+    unsafe {
+        set_header_value(
+            header_kind,
+            name.as_ptr(),
+            name.len() as u32,
+            value.as_ptr(),
+            value.len() as u32,
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads the body of the current request or response, passing it through to the host.
+///
+/// The host returns the body in chunks: the low 32 bits of each result hold the number
+/// of bytes read and the high 32 bits are `1` once the end of the body is reached. Each
+/// chunk is written back as soon as it is read, so the body is never held in full.
 ///
 /// # Arguments
 ///
 /// * `body_kind` - The kind of body to read (0 for request, 1 for response).
+/// * `max_bytes` - The largest body kept for parsing; `0` keeps any size.
 ///
 /// # Returns
 ///
-/// Returns the body as a vector of bytes, or an error if reading fails.
+/// Returns the body, kept only if it fits within `max_bytes`, with its exact size, or an
+/// error if reading fails.
 #[cfg(feature = "wasm")]
-pub fn host_read_body(body_kind: u32) -> Result<Vec<u8>> {
-    let mut buffer = Vec::with_capacity(4096);
-    let read = unsafe { read_body(body_kind, buffer.as_mut_ptr(), 4096) };
+pub fn host_read_body(body_kind: u32, max_bytes: usize) -> Result<ReadBody> {
+    let mut body = ReadBody::new(max_bytes);
+    let mut chunk = vec![0u8; READ_BODY_CHUNK_SIZE];
 
-    if read < 0 {
-        Err(TreblleError::HostFunction("Error reading body".to_string()))
-    } else {
-        unsafe {
-            buffer.set_len(read as usize);
+    loop {
+        let result = unsafe { read_body(body_kind, chunk.as_mut_ptr(), chunk.len() as u32) };
+
+        if result < 0 {
+            return Err(TreblleError::HostFunction("Error reading body".to_string()));
+        }
+
+        let read = (result & 0xffff_ffff) as usize;
+        let eof = (result >> 32) & 1 == 1;
+        let data = &chunk[..read.min(chunk.len())];
+
+        // The first write replaces the body that was consumed, later writes append to it.
+        if !data.is_empty() {
+            host_write_body(body_kind, data)?;
+        }
+        body.push(data);
+
+        // A chunk without data that is not flagged as the end would loop forever.
+        if eof || read == 0 {
+            return Ok(body);
         }
-        Ok(buffer)
     }
 }
 
@@ -7,18 +7,38 @@ use std::collections::HashMap;
 use std::time::Instant;
 use std::sync::MutexGuard;
 
+use serde_json::Value;
+
 #[cfg(feature = "wasm")]
 use crate::host_functions::*;
 
 #[cfg(feature = "wasm")]
 use crate::{HTTP_CLIENT};
 
-use crate::constants::{HEADER_CONTENT_TYPE, REQUEST_KIND, RESPONSE_KIND};
+#[cfg(feature = "wasm")]
+use crate::capture_policy::{self, CapturePolicy};
+#[cfg(feature = "wasm")]
+use crate::charset;
+#[cfg(feature = "wasm")]
+use crate::request_context::{self, RequestContext};
+#[cfg(feature = "wasm")]
+use crate::request_id;
+#[cfg(feature = "wasm")]
+use crate::streaming;
+#[cfg(feature = "wasm")]
+use crate::trace_context::{TraceContext, TRACEPARENT_HEADER};
+
+use crate::constants::{HEADER_CONTENT_TYPE, HEADER_VALUE_SEPARATOR, REQUEST_KIND, RESPONSE_KIND};
 use crate::error::{Result, TreblleError};
+use crate::error_details;
 use crate::logger::{log, LogLevel};
 use crate::payload::Payload;
-use crate::schema::ErrorInfo;
-use crate::{BLACKLIST, CONFIG};
+use crate::jsonrpc;
+use crate::schema::{ErrorInfo, JsonRpcCall};
+use crate::route_filter::RouteRequest;
+use crate::config::Config;
+use crate::utils::{self, get_header_value, ReadBody};
+use crate::{CONFIG, ROUTE_FILTER, SAMPLER, TAGS, UNBUFFERED_ROUTES, USER_ID};
 
 /// The main handler for HTTP requests and responses
 pub struct HttpHandler;
@@ -29,36 +49,92 @@ impl HttpHandler {
     /// This function handles the incoming HTTP request, checks if it should be processed,
     /// and sends the relevant data to the Treblle API if necessary.
     ///
+    /// # Arguments
+    ///
+    /// * `context` - The request context, updated with the capture decision for the response
+    ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the request was processed successfully, or an error if something went wrong.
     #[cfg(feature = "wasm")]
-    pub fn process_request(&self) -> Result<()> {
-        let start_time = Instant::now();
+    pub fn process_request(&self, context: &mut RequestContext) -> Result<()> {
+        let start_time = context.start_time.unwrap_or_else(Instant::now);
         log(LogLevel::Debug, "Processing request...");
 
         let uri = self.get_uri()?;
-        if BLACKLIST.is_blacklisted(&uri) {
-            log(LogLevel::Info, "URL is blacklisted, skipping Treblle API");
+        let method = self.get_method()?;
+        let headers = self.get_headers(REQUEST_KIND)?;
+        let host = get_header_value(&headers, "Host").unwrap_or_default();
+
+        let route_request = RouteRequest {
+            method: &method,
+            host: &host,
+            uri: &uri,
+            headers: &headers,
+        };
+        if !ROUTE_FILTER.should_capture(&route_request) {
+            log(LogLevel::Info, "Route is filtered out, skipping Treblle API");
             return Ok(());
         }
 
-        let content_type = self.get_content_type()?;
-        if !crate::payload::is_json(&content_type) {
-            log(LogLevel::Info, "Non-JSON request, skipping Treblle API");
+        // The trace context is propagated whether or not the request is sampled.
+        context.trace = self
+            .trace_context(&headers)
+            .map(|trace| trace.to_trace_info());
+        context.request_id = self.request_id(&headers);
+        context.tags = TAGS.extract(&uri, &headers);
+        context.user = USER_ID.resolve(&headers);
+
+        // Streaming routes and requests for a stream are never buffered, their responses
+        // are reported without a body.
+        context.buffer_response = CONFIG.buffer_response
+            && !UNBUFFERED_ROUTES.matches(&route_request)
+            && !streaming::is_streaming_request(&headers);
+
+        // Sampling runs before the body is read so unsampled requests stay cheap.
+        if !SAMPLER.should_sample(&route_request) {
+            context.capture_errors = SAMPLER.always_capture_errors();
+            log(LogLevel::Info, "Request not sampled, skipping Treblle API");
             return Ok(());
         }
-
-        let method = self.get_method()?;
-        let headers = self.get_headers(REQUEST_KIND)?;
-        let body = self.read_body(REQUEST_KIND)?;
-
-        self.write_body(REQUEST_KIND, &body)?;
+        context.sampled = true;
+
+        // The content type only decides whether the body is read; requests without a
+        // captured body (GET, DELETE, HEAD, ...) are still recorded.
+        let content_type = self.get_content_type().unwrap_or_default();
+        let body = if self.should_read_body(&content_type, &CONFIG) {
+            self.read_body(REQUEST_KIND)?
+        } else {
+            log(LogLevel::Debug, "Request body is not captured for this content type");
+            ReadBody::default()
+        };
 
         let mut payload = Payload::new();
-        self.update_payload(&mut payload, method, uri, headers, &body)?;
+        self.update_payload(&mut payload, method, uri, headers, body.bytes())?;
+        if body.is_truncated() {
+            let request = &mut payload.data.data.request;
+            request.size = body.size();
+            request.body_error = Some(body_too_large(&body));
+        }
+        if let Some(received_at) = context.received_at {
+            payload.data.data.request.timestamp = received_at.to_rfc3339();
+        }
+        payload.data.data.trace = context.trace.clone();
+        payload.data.data.request.request_id = context.request_id.clone();
+        payload.data.data.tags = context.tags.clone();
+        payload.data.data.user = context.user.clone();
+        context.jsonrpc_calls = payload.data.data.request.jsonrpc.clone();
+
+        if CONFIG.capture_policy == CapturePolicy::ErrorsOrSlow {
+            log(LogLevel::Debug, "Holding request data until the response is known");
+            context.pending_payload = Some(payload);
+            return Ok(());
+        }
 
-        self.send_to_treblle(&payload, start_time)?;
+        payload.update_timing_info(start_time, None, None);
+        for event in self.jsonrpc_events(payload, &context.jsonrpc_calls, CONFIG.split_jsonrpc_batches) {
+            self.send_to_treblle(&event, start_time)?;
+        }
 
         log(LogLevel::Debug, "Request processing completed successfully");
 
@@ -72,15 +148,18 @@ impl HttpHandler {
     ///
     /// # Arguments
     ///
-    /// * `_req_ctx` - The request context (unused)
+    /// * `req_ctx` - The ID of the request context stored by `handle_request`
     /// * `is_error` - Indicates if the response is an error
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the response was processed successfully, or an error if something went wrong.
     #[cfg(feature = "wasm")]
-    pub fn process_response(&self, _req_ctx: i32, is_error: i32) -> Result<()> {
-        if !CONFIG.buffer_response {
+    pub fn process_response(&self, req_ctx: i32, is_error: i32) -> Result<()> {
+        let context = request_context::take(req_ctx).unwrap_or_default();
+        let holds_request = CONFIG.capture_policy == CapturePolicy::ErrorsOrSlow;
+
+        if !CONFIG.buffer_response && !holds_request {
             log(
                 LogLevel::Info,
                 "Not processing response, buffer_response is not enabled",
@@ -89,25 +168,67 @@ impl HttpHandler {
         }
 
         let start_time = Instant::now();
+        let status_code = host_get_status_code();
+        let failed = is_error != 0 || status_code >= 400;
+
+        if !context.should_capture_response(failed) {
+            log(LogLevel::Info, "Response not sampled, skipping Treblle API");
+            return Ok(());
+        }
+
+        let request_start = context.start_time.unwrap_or(start_time);
+        let elapsed = request_start.elapsed();
+        if !capture_policy::should_ship(&CONFIG, status_code, is_error != 0, elapsed) {
+            log(LogLevel::Info, "Request is neither an error nor slow, skipping Treblle API");
+            return Ok(());
+        }
 
         log(LogLevel::Debug, "Processing response...");
 
-        let mut payload = Payload::new();
+        let mut payload = context.pending_payload.unwrap_or_else(Payload::new);
+        payload.data.data.trace = context.trace.clone();
+        payload.data.data.request.request_id = context.request_id.clone();
+        payload.data.data.tags = context.tags.clone();
+        payload.data.data.user = context.user.clone();
         let headers = self.get_headers(RESPONSE_KIND)?;
-        let body = self.read_body(RESPONSE_KIND)?;
-        let status_code = host_get_status_code();
-
-        self.write_body(RESPONSE_KIND, &body)?;
 
-        payload.update_response_info(status_code, headers, &body, start_time);
+        // Without buffering the response body cannot be read, only its status and headers.
+        // Streams are left untouched even when buffered, reading them would hold them in memory.
+        let streaming = streaming::is_streaming_response(&headers);
+        let body = if context.buffer_response && !streaming {
+            self.read_body(RESPONSE_KIND)?
+        } else {
+            if streaming {
+                log(LogLevel::Debug, "Streaming response, capturing metadata only");
+            }
+            ReadBody::default()
+        };
+
+        let content_type = get_header_value(&headers, HEADER_CONTENT_TYPE).unwrap_or_default();
+        let encoding = charset::extract_charset(&content_type);
+        payload.update_response_info(status_code, headers, body.bytes(), request_start);
+        if body.is_truncated() {
+            let response = &mut payload.data.data.response;
+            response.size = body.size();
+            response.body_error = Some(body_too_large(&body));
+        }
 
-        self.update_payload_server_info(&mut payload)?;
+        self.update_payload_server_info(&mut payload, encoding)?;
 
-        if is_error != 0 || status_code >= 400 {
-            payload.add_error(self.create_error_info(status_code));
+        if failed {
+            let error = self.create_error_info(
+                status_code,
+                is_error != 0,
+                payload.data.data.response.body.as_ref(),
+                &content_type,
+            );
+            payload.add_error(error);
         }
 
-        self.send_to_treblle(&payload, start_time)?;
+        payload.update_timing_info(request_start, context.forwarded_at, Some(start_time));
+        for event in self.jsonrpc_events(payload, &context.jsonrpc_calls, CONFIG.split_jsonrpc_batches) {
+            self.send_to_treblle(&event, start_time)?;
+        }
 
         log(LogLevel::Debug, "Response processing completed successfully");
 
@@ -124,13 +245,15 @@ impl HttpHandler {
 
     #[cfg(feature = "wasm")]
     fn get_content_type(&self) -> Result<String> {
-        host_get_header_values(REQUEST_KIND, HEADER_CONTENT_TYPE).map_err(|e| {
-            log(
-                LogLevel::Error,
-                &format!("Failed to get Content-Type: {}", e),
-            );
-            TreblleError::HostFunction(e.to_string())
-        })
+        host_get_header_values(REQUEST_KIND, HEADER_CONTENT_TYPE)
+            .map(|values| strip_terminator(&values).to_string())
+            .map_err(|e| {
+                log(
+                    LogLevel::Error,
+                    &format!("Failed to get Content-Type: {}", e),
+                );
+                TreblleError::HostFunction(e.to_string())
+            })
     }
 
     #[cfg(feature = "wasm")]
@@ -156,13 +279,9 @@ impl HttpHandler {
             TreblleError::HostFunction(e.to_string())
         })?;
 
-        let mut headers = HashMap::new();
-
-        for name in header_names.split(',').filter(|s| !s.is_empty()) {
-            if let Ok(values) = host_get_header_values(header_kind, name) {
-                headers.insert(name.to_string(), values);
-            }
-        }
+        let headers = headers_from_host(&header_names, |name| {
+            host_get_header_values(header_kind, name).ok()
+        });
 
         log(
             LogLevel::Debug,
@@ -173,21 +292,13 @@ impl HttpHandler {
     }
 
     #[cfg(feature = "wasm")]
-    fn read_body(&self, body_kind: u32) -> Result<Vec<u8>> {
-        host_read_body(body_kind).map_err(|e| {
+    fn read_body(&self, body_kind: u32) -> Result<ReadBody> {
+        host_read_body(body_kind, CONFIG.max_body_bytes).map_err(|e| {
             log(LogLevel::Error, &format!("Failed to read body: {}", e));
             TreblleError::HostFunction(e.to_string())
         })
     }
 
-    #[cfg(feature = "wasm")]
-    fn write_body(&self, body_kind: u32, body: &[u8]) -> Result<()> {
-        host_write_body(body_kind, body).map_err(|e| {
-            log(LogLevel::Error, &format!("Failed to write body: {}", e));
-            TreblleError::HostFunction(e.to_string())
-        })
-    }
-
     #[cfg(feature = "wasm")]
     fn update_payload(
         &self,
@@ -197,16 +308,100 @@ impl HttpHandler {
         headers: HashMap<String, String>,
         body: &[u8],
     ) -> Result<()> {
-        payload.update_request_info(method, uri, headers, body);
+        // Without the peer address only forwarding headers can identify the client.
+        let source_addr = host_get_source_addr()
+            .map_err(|e| log(LogLevel::Warn, &format!("Failed to get source address: {}", e)))
+            .ok();
+        payload.update_request_info(method, uri, headers, body, source_addr.as_deref());
         payload.update_language_info();
 
-        self.update_payload_server_info(payload)?;
+        self.update_payload_server_info(payload, None)?;
 
         Ok(())
     }
 
+    /// Reads the trace context of the request.
+    ///
+    /// When the request has no `traceparent` and propagation is enabled, one is injected
+    /// into the upstream request, built from the B3 headers or freshly generated.
+    ///
+    /// # Returns
+    ///
+    /// Returns the trace context, or `None` if the request has none and none was injected.
     #[cfg(feature = "wasm")]
-    fn update_payload_server_info(&self, payload: &mut Payload) -> Result<()> {
+    fn trace_context(&self, headers: &HashMap<String, String>) -> Option<TraceContext> {
+        let context = TraceContext::from_headers(headers, CONFIG.accept_b3_headers);
+
+        if !CONFIG.propagate_trace_context
+            || context.as_ref().is_some_and(|c| c.source == "traceparent")
+        {
+            return context;
+        }
+
+        let context = match context {
+            Some(context) => context,
+            None => TraceContext::generate()
+                .map_err(|e| {
+                    log(
+                        LogLevel::Warn,
+                        &format!("Failed to generate trace context: {}", e),
+                    )
+                })
+                .ok()?,
+        };
+
+        match host_set_header_value(REQUEST_KIND, TRACEPARENT_HEADER, &context.traceparent()) {
+            Ok(()) => Some(context),
+            Err(e) => {
+                log(LogLevel::Warn, &format!("Failed to set traceparent: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Reads the request ID, generating one and setting it on the request if it is missing.
+    ///
+    /// The ID is echoed on the response so clients can quote it.
+    ///
+    /// # Returns
+    ///
+    /// Returns the request ID, or `None` if `requestIdHeader` is empty or no ID could be
+    /// generated.
+    #[cfg(feature = "wasm")]
+    fn request_id(&self, headers: &HashMap<String, String>) -> Option<String> {
+        let header = CONFIG.request_id_header.as_str();
+        if header.is_empty() {
+            return None;
+        }
+
+        let (id, generated) = request_id::resolve(headers, header)
+            .map_err(|e| {
+                log(
+                    LogLevel::Warn,
+                    &format!("Failed to generate request ID: {}", e),
+                )
+            })
+            .ok()?;
+
+        if generated {
+            if let Err(e) = host_set_header_value(REQUEST_KIND, header, &id) {
+                log(LogLevel::Warn, &format!("Failed to set {}: {}", header, e));
+            }
+        }
+
+        if let Err(e) = host_set_header_value(RESPONSE_KIND, header, &id) {
+            log(LogLevel::Warn, &format!("Failed to echo {}: {}", header, e));
+        }
+
+        Some(id)
+    }
+
+    #[cfg(feature = "wasm")]
+    fn update_payload_server_info(
+        &self,
+        payload: &mut Payload,
+        encoding: Option<String>,
+    ) -> Result<()> {
         let protocol = host_get_protocol_version().map_err(|e| {
             log(
                 LogLevel::Error,
@@ -215,19 +410,54 @@ impl HttpHandler {
             TreblleError::HostFunction(e.to_string())
         })?;
 
-        payload.update_server_info(protocol);
+        payload.update_server_info(protocol, encoding);
 
         Ok(())
     }
 
-    fn create_error_info(&self, status_code: u32) -> ErrorInfo {
-        ErrorInfo {
-            source: "response".to_string(),
-            error_type: "HTTP Error".to_string(),
-            message: format!("HTTP status code: {}", status_code),
-            file: String::new(),
-            line: 0,
+    /// Splits batched JSON-RPC calls into separate events if enabled, and records the
+    /// JSON-RPC errors found in each response body.
+    fn jsonrpc_events(&self, payload: Payload, calls: &[JsonRpcCall], split: bool) -> Vec<Payload> {
+        let mut events = if split {
+            jsonrpc::split_batch(&payload, calls)
+        } else {
+            vec![payload]
+        };
+
+        for event in &mut events {
+            if let Some(body) = event.data.data.response.body.clone() {
+                for error in jsonrpc::response_errors(&body, calls) {
+                    event.add_error(error);
+                }
+            }
         }
+
+        events
+    }
+
+    /// Checks if the request body is read for a content type.
+    ///
+    /// Requests without a content type carry no body worth reading.
+    fn should_read_body(&self, content_type: &str, config: &Config) -> bool {
+        !content_type.is_empty() && utils::captures_body(content_type, config)
+    }
+
+    /// Creates the error of a failed response.
+    ///
+    /// # Arguments
+    ///
+    /// * `status_code` - The HTTP status code of the response.
+    /// * `traefik_error` - Whether Traefik, not the upstream service, raised the error.
+    /// * `body` - The masked response body, read for problem details and error messages.
+    /// * `content_type` - The `Content-Type` of the response.
+    fn create_error_info(
+        &self,
+        status_code: u32,
+        traefik_error: bool,
+        body: Option<&Value>,
+        content_type: &str,
+    ) -> ErrorInfo {
+        error_details::response_error(status_code, traefik_error, body, content_type)
     }
 
     #[cfg(feature = "wasm")]
@@ -269,21 +499,189 @@ impl HttpHandler {
     }
 }
 
+/// Builds the headers from the host buffers, where every header name and every value
+/// ends with a NUL byte.
+///
+/// # Arguments
+///
+/// * `names` - The buffer of header names.
+/// * `values` - Reads the buffer of values of a header.
+///
+/// # Returns
+///
+/// Returns the headers, with the values of a repeated header joined by
+/// `HEADER_VALUE_SEPARATOR`.
+fn headers_from_host(
+    names: &str,
+    values: impl Fn(&str) -> Option<String>,
+) -> HashMap<String, String> {
+    names
+        .split(HEADER_VALUE_SEPARATOR)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            values(name).map(|values| (name.to_string(), strip_terminator(&values).to_string()))
+        })
+        .collect()
+}
+
+/// Explains why a body that outgrew `maxBodyBytes` was not captured.
+fn body_too_large(body: &ReadBody) -> String {
+    format!(
+        "Body of {} bytes exceeds maxBodyBytes ({} bytes)",
+        body.size(),
+        CONFIG.max_body_bytes
+    )
+}
+
+/// Strips the NUL byte ending a host buffer of header values.
+fn strip_terminator(values: &str) -> &str {
+    values
+        .strip_suffix(HEADER_VALUE_SEPARATOR)
+        .unwrap_or(values)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
-    use crate::route_blacklist::RouteBlacklist;
+    use crate::masking::Masker;
+    use crate::route_filter::RouteFilter;
+    use crate::request_id;
+    use crate::trace_context::TraceContext;
+
+    #[test]
+    fn test_graphql_request_body_is_read() {
+        let handler = HttpHandler;
+        let config = Config {
+            graphql_operation_as_route: true,
+            ..Config::fallback()
+        };
+
+        assert!(handler.should_read_body("application/graphql", &config));
+        assert!(handler.should_read_body("application/json", &config));
+        assert!(!handler.should_read_body("text/plain", &config));
+        assert!(!handler.should_read_body("", &config));
+
+        let headers = HashMap::from([(
+            "Content-Type".to_string(),
+            "application/graphql".to_string(),
+        )]);
+        let request = utils::parse_request(
+            "POST".to_string(),
+            "/graphql".to_string(),
+            headers,
+            b"query GetUser { user(id: 1) { name } }",
+            None,
+            &config,
+            &Masker::from_config(&config).unwrap(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let graphql = request
+            .graphql
+            .expect("GraphQL operation should be detected");
+        assert_eq!(graphql.operation_name, Some("GetUser".to_string()));
+        assert_eq!(request.route_path, Some("/graphql/GetUser".to_string()));
+    }
+
+    #[test]
+    fn test_headers_from_host() {
+        let values = HashMap::from([
+            (
+                "Traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\0",
+            ),
+            ("Set-Cookie", "a=1\0b=2\0"),
+        ]);
+
+        let headers = headers_from_host("Traceparent\0Set-Cookie\0X-Missing\0", |name| {
+            values.get(name).map(|v| v.to_string())
+        });
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["Set-Cookie"], "a=1\0b=2");
+
+        let trace = TraceContext::from_headers(&headers, false).expect("traceparent is valid");
+        assert_eq!(trace.source, "traceparent");
+        assert_eq!(trace.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn test_request_id_from_host_is_kept() {
+        let headers = headers_from_host("X-Request-Id\0", |_| Some("req-8812\0".to_string()));
+
+        assert_eq!(
+            request_id::resolve(&headers, "X-Request-Id").unwrap(),
+            ("req-8812".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn test_body_too_large() {
+        let mut body = ReadBody::new(CONFIG.max_body_bytes);
+        body.push(&vec![b'a'; CONFIG.max_body_bytes + 1]);
+
+        assert!(body.is_truncated());
+        assert_eq!(
+            body_too_large(&body),
+            format!(
+                "Body of {} bytes exceeds maxBodyBytes ({} bytes)",
+                CONFIG.max_body_bytes + 1,
+                CONFIG.max_body_bytes
+            )
+        );
+    }
 
     #[test]
     fn test_create_error_info() {
         let handler = HttpHandler;
-        let error_info = handler.create_error_info(404);
+        let error_info = handler.create_error_info(404, false, None, "");
 
         assert_eq!(error_info.source, "response");
         assert_eq!(error_info.error_type, "HTTP Error");
         assert_eq!(error_info.message, "HTTP status code: 404");
         assert!(error_info.file.is_empty());
         assert_eq!(error_info.line, 0);
+
+        let body = serde_json::json!({ "title": "Not Found", "detail": "Order 42 does not exist" });
+        let error_info =
+            handler.create_error_info(404, false, Some(&body), "application/problem+json");
+
+        assert_eq!(error_info.error_type, "Not Found");
+        assert_eq!(error_info.message, "Order 42 does not exist");
+
+        let error_info = handler.create_error_info(502, true, None, "");
+
+        assert_eq!(error_info.source, "traefik");
+        assert_eq!(error_info.message, "HTTP status code: 502");
+    }
+
+    #[test]
+    fn test_jsonrpc_events() {
+        let handler = HttpHandler;
+        let calls = vec![
+            JsonRpcCall {
+                method: "sum".to_string(),
+                id: serde_json::json!(1),
+            },
+            JsonRpcCall {
+                method: "divide".to_string(),
+                id: serde_json::json!(2),
+            },
+        ];
+        let mut payload = Payload::new();
+        payload.data.data.response.body = Some(serde_json::json!([
+            { "jsonrpc": "2.0", "result": 3, "id": 1 },
+            { "jsonrpc": "2.0", "error": { "code": -32000, "message": "Division by zero" }, "id": 2 }
+        ]));
+
+        let combined = handler.jsonrpc_events(payload.clone(), &calls, false);
+        assert_eq!(combined.len(), 1);
+        assert_eq!(combined[0].data.data.errors.len(), 1);
+
+        let split = handler.jsonrpc_events(payload, &calls, true);
+        assert_eq!(split.len(), 2);
+        assert!(split[0].data.data.errors.is_empty());
+        assert_eq!(split[1].data.data.errors.len(), 1);
     }
 }
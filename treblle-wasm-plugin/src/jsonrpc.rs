@@ -0,0 +1,233 @@
+//! JSON-RPC module for the Treblle middleware.
+//!
+//! This module recognizes JSON-RPC 2.0 requests, turns error objects in the response
+//! into `ErrorInfo` entries and can split batched calls into separate events.
+
+use serde_json::Value;
+
+use crate::payload::Payload;
+use crate::schema::{ErrorInfo, JsonRpcCall};
+
+const JSONRPC_VERSION: &str = "2.0";
+
+/// Detects the JSON-RPC calls in a request body.
+///
+/// # Arguments
+///
+/// * `body` - The parsed request body, either a single call or a batch array.
+///
+/// # Returns
+///
+/// Returns the `method` and `id` of each call, or an empty vector if the body is not JSON-RPC.
+pub fn detect_calls(body: &Value) -> Vec<JsonRpcCall> {
+    match body {
+        Value::Object(_) => parse_call(body).into_iter().collect(),
+        Value::Array(items) => {
+            let calls: Vec<JsonRpcCall> = items.iter().filter_map(parse_call).collect();
+            // Only treat the array as a batch if every element is a call.
+            if calls.len() == items.len() {
+                calls
+            } else {
+                Vec::new()
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Converts the JSON-RPC error objects of a response body into `ErrorInfo` entries.
+///
+/// Errors are correlated with the request calls by `id` so the failing method is reported.
+///
+/// # Arguments
+///
+/// * `body` - The parsed response body, either a single response or a batch array.
+/// * `calls` - The calls of the matching request.
+///
+/// # Returns
+///
+/// Returns one `ErrorInfo` per error object.
+pub fn response_errors(body: &Value, calls: &[JsonRpcCall]) -> Vec<ErrorInfo> {
+    let responses = match body {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(_) => vec![body],
+        _ => Vec::new(),
+    };
+
+    responses
+        .into_iter()
+        .filter(|response| is_jsonrpc(response))
+        .filter_map(|response| {
+            let error = response.get("error")?;
+            let id = response.get("id").cloned().unwrap_or(Value::Null);
+            let method = calls
+                .iter()
+                .find(|call| !id.is_null() && call.id == id)
+                .map(|call| call.method.as_str())
+                .unwrap_or("unknown");
+
+            Some(ErrorInfo {
+                source: "response".to_string(),
+                error_type: "JSON-RPC Error".to_string(),
+                message: format!(
+                    "{} (code: {}, method: {}, id: {})",
+                    error
+                        .get("message")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("Unknown error"),
+                    error.get("code").unwrap_or(&Value::Null),
+                    method,
+                    id
+                ),
+                file: String::new(),
+                line: 0,
+            })
+        })
+        .collect()
+}
+
+/// Splits a payload holding a batch of calls into one payload per call.
+///
+/// Each resulting payload keeps the request element of its call and the response element
+/// with the same `id`; notifications get no response body.
+///
+/// # Arguments
+///
+/// * `payload` - The payload to split.
+/// * `calls` - The calls of the batch, in request order.
+///
+/// # Returns
+///
+/// Returns one payload per call, or a copy of `payload` if it is not a batch.
+pub fn split_batch(payload: &Payload, calls: &[JsonRpcCall]) -> Vec<Payload> {
+    if calls.len() <= 1 {
+        return vec![payload.clone()];
+    }
+
+    let request_items = payload.data.data.request.body.as_ref().and_then(|body| {
+        body.as_array()
+            .filter(|items| items.len() == calls.len())
+            .cloned()
+    });
+    let response_items = payload
+        .data
+        .data
+        .response
+        .body
+        .as_ref()
+        .and_then(|body| body.as_array().cloned());
+
+    calls
+        .iter()
+        .enumerate()
+        .map(|(i, call)| {
+            let mut split = payload.clone();
+            let data = &mut split.data.data;
+
+            data.request.jsonrpc = vec![call.clone()];
+            if let Some(items) = &request_items {
+                data.request.body = Some(items[i].clone());
+            }
+            if let Some(items) = &response_items {
+                data.response.body = Some(
+                    items
+                        .iter()
+                        .find(|item| !call.id.is_null() && item.get("id") == Some(&call.id))
+                        .cloned()
+                        .unwrap_or(Value::Null),
+                );
+            }
+
+            split
+        })
+        .collect()
+}
+
+fn parse_call(value: &Value) -> Option<JsonRpcCall> {
+    if !is_jsonrpc(value) {
+        return None;
+    }
+
+    Some(JsonRpcCall {
+        method: value.get("method")?.as_str()?.to_string(),
+        id: value.get("id").cloned().unwrap_or(Value::Null),
+    })
+}
+
+fn is_jsonrpc(value: &Value) -> bool {
+    value.get("jsonrpc").and_then(|v| v.as_str()) == Some(JSONRPC_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn call(method: &str, id: Value) -> JsonRpcCall {
+        JsonRpcCall {
+            method: method.to_string(),
+            id,
+        }
+    }
+
+    #[test]
+    fn test_detect_calls() {
+        assert_eq!(
+            detect_calls(&json!({ "jsonrpc": "2.0", "method": "sum", "params": [1, 2], "id": 1 })),
+            vec![call("sum", json!(1))]
+        );
+        assert_eq!(
+            detect_calls(&json!([
+                { "jsonrpc": "2.0", "method": "sum", "id": "a" },
+                { "jsonrpc": "2.0", "method": "notify" }
+            ])),
+            vec![call("sum", json!("a")), call("notify", Value::Null)]
+        );
+        assert!(detect_calls(&json!({ "method": "sum" })).is_empty());
+        assert!(detect_calls(&json!([{ "jsonrpc": "2.0", "method": "sum" }, 1])).is_empty());
+    }
+
+    #[test]
+    fn test_response_errors() {
+        let calls = vec![call("sum", json!(1)), call("divide", json!(2))];
+        let body = json!([
+            { "jsonrpc": "2.0", "result": 3, "id": 1 },
+            { "jsonrpc": "2.0", "error": { "code": -32000, "message": "Division by zero" }, "id": 2 }
+        ]);
+
+        let errors = response_errors(&body, &calls);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error_type, "JSON-RPC Error");
+        assert_eq!(
+            errors[0].message,
+            "Division by zero (code: -32000, method: divide, id: 2)"
+        );
+    }
+
+    #[test]
+    fn test_split_batch() {
+        let calls = vec![call("sum", json!(1)), call("notify", Value::Null)];
+        let mut payload = Payload::new();
+        payload.data.data.request.body = Some(json!([
+            { "jsonrpc": "2.0", "method": "sum", "id": 1 },
+            { "jsonrpc": "2.0", "method": "notify" }
+        ]));
+        payload.data.data.response.body = Some(json!([{ "jsonrpc": "2.0", "result": 3, "id": 1 }]));
+
+        let split = split_batch(&payload, &calls);
+
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].data.data.request.jsonrpc, vec![calls[0].clone()]);
+        assert_eq!(
+            split[0].data.data.request.body.as_ref().unwrap()["method"],
+            "sum"
+        );
+        assert_eq!(
+            split[0].data.data.response.body.as_ref().unwrap()["result"],
+            3
+        );
+        assert_eq!(split[1].data.data.response.body, Some(Value::Null));
+        assert_eq!(split_batch(&payload, &calls[..1]).len(), 1);
+    }
+}
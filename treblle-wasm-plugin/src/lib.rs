@@ -10,33 +10,109 @@ mod bindings;
 #[cfg(feature = "wasm")]
 mod host_functions;
 
+mod body_parser;
+mod capture_policy;
 mod certs;
+mod charset;
+mod client_ip;
 mod config;
 mod constants;
+mod decompression;
 mod error;
+mod error_details;
+mod graphql;
 mod http_handler;
+mod jsonrpc;
 mod logger;
+mod masking;
 mod payload;
-mod route_blacklist;
+mod request_context;
+mod request_id;
+mod route_filter;
+mod route_template;
+mod sampling;
 mod schema;
+mod streaming;
+mod tags;
+mod trace_context;
+mod user_id;
 mod utils;
 mod wasi_http_client;
 
 use std::sync::Mutex;
+#[cfg(feature = "wasm")]
+use std::time::Instant;
+#[cfg(feature = "wasm")]
+use chrono::Utc;
 use once_cell::sync::Lazy;
 
 #[cfg(feature = "wasm")]
 use bindings::exports::traefik::http_handler::handler::Guest;
 
+use client_ip::ClientIpResolver;
 use config::Config;
 use http_handler::HttpHandler;
 use logger::{log, LogLevel};
-use route_blacklist::RouteBlacklist;
+use masking::Masker;
+#[cfg(feature = "wasm")]
+use request_context::RequestContext;
+use route_filter::RouteFilter;
+use route_template::RouteNormalizer;
+use sampling::Sampler;
+use streaming::UnbufferedRoutes;
+use tags::TagExtractor;
+use user_id::UserIdResolver;
 use crate::wasi_http_client::WasiHttpClient;
 
 pub static CONFIG: Lazy<Config> = Lazy::new(Config::get_or_fallback);
-pub static BLACKLIST: Lazy<RouteBlacklist> =
-    Lazy::new(|| RouteBlacklist::new(&CONFIG.route_blacklist));
+pub static ROUTE_FILTER: Lazy<RouteFilter> = Lazy::new(|| {
+    RouteFilter::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid route rules, capturing no routes: {}", e));
+        RouteFilter::capture_none()
+    })
+});
+pub static SAMPLER: Lazy<Sampler> = Lazy::new(|| {
+    Sampler::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid sampling settings, capturing all requests: {}", e));
+        Sampler::default()
+    })
+});
+pub static UNBUFFERED_ROUTES: Lazy<UnbufferedRoutes> = Lazy::new(|| {
+    UnbufferedRoutes::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid unbuffered routes, buffering all routes: {}", e));
+        UnbufferedRoutes::default()
+    })
+});
+pub static CLIENT_IP: Lazy<ClientIpResolver> = Lazy::new(|| {
+    ClientIpResolver::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid trusted proxies, using the peer address: {}", e));
+        ClientIpResolver::default()
+    })
+});
+pub static ROUTE_NORMALIZER: Lazy<RouteNormalizer> = Lazy::new(|| {
+    RouteNormalizer::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid route templates, not grouping routes: {}", e));
+        RouteNormalizer::default()
+    })
+});
+pub static MASKER: Lazy<Masker> = Lazy::new(|| {
+    Masker::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid sensitive keys regex, masking the default keys: {}", e));
+        Masker::default()
+    })
+});
+pub static TAGS: Lazy<TagExtractor> = Lazy::new(|| {
+    TagExtractor::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid tag settings, not tagging events: {}", e));
+        TagExtractor::default()
+    })
+});
+pub static USER_ID: Lazy<UserIdResolver> = Lazy::new(|| {
+    UserIdResolver::from_config(&CONFIG).unwrap_or_else(|e| {
+        log(LogLevel::Error, &format!("Invalid user ID settings, not identifying users: {}", e));
+        UserIdResolver::default()
+    })
+});
 
 #[cfg(feature = "wasm")]
 pub static HTTP_CLIENT: Lazy<Mutex<WasiHttpClient>> = Lazy::new(|| {
@@ -54,29 +130,44 @@ impl Guest for HttpHandler {
     ///
     /// # Returns
     ///
-    /// Returns 1 to indicate that Traefik should continue processing the request.
+    /// Returns the request context ID in the high 32 bits and 1 in the low 32 bits to
+    /// indicate that Traefik should continue processing the request.
     fn handle_request() -> i64 {
+        let mut context = RequestContext {
+            start_time: Some(Instant::now()),
+            received_at: Some(Utc::now()),
+            ..Default::default()
+        };
+
         logger::init();
 
         Lazy::force(&HTTP_CLIENT);
         
         log(LogLevel::Debug, "Handling request in WASM module");
 
-        if CONFIG.buffer_response {
+        if let Err(e) = HttpHandler.process_request(&mut context) {
+            log(LogLevel::Error, &format!("Error processing request: {}", e));
+        }
+
+        if context.should_buffer_response() {
             let features = host_functions::host_enable_features(2); // Enable FeatureBufferResponse
             log(LogLevel::Info, &format!("Enabled features: {}", features));
         }
 
-        if let Err(e) = HttpHandler.process_request() {
-            log(LogLevel::Error, &format!("Error processing request: {}", e));
-        }
+        context.forwarded_at = Some(Instant::now());
+
+        let ctx_id = if context.sampled || context.capture_errors {
+            request_context::store(context)
+        } else {
+            0
+        };
 
         log(
             LogLevel::Info,
             "Request processed. Letting Traefik continue processing the request with next middleware...",
         );
 
-        1 // Always continue processing the request
+        (i64::from(ctx_id) << 32) | 1 // Always continue processing the request
     }
 
     /// Handle an HTTP response
@@ -0,0 +1,561 @@
+//! Masking module for the Treblle middleware.
+//!
+//! This module implements the masking engine that redacts sensitive values
+//! from JSON bodies and headers before they are sent to the Treblle API.
+
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::config::Config;
+use crate::constants::{
+    CREDENTIAL_HEADERS, DEFAULT_SENSITIVE_KEYS_REGEX, HEADER_VALUE_SEPARATOR, SET_COOKIE_ATTRIBUTES,
+};
+use crate::error::{Result, TreblleError};
+use crate::schema::MaskedField;
+
+/// Minimum length of a string before it is considered as a base64 candidate.
+const MIN_BASE64_LEN: usize = 8;
+
+/// Rule name recorded when a key or header name matches `sensitiveKeysRegex`.
+pub(crate) const RULE_SENSITIVE_KEYS: &str = "sensitive_keys_regex";
+/// Rule name recorded when a built-in credential header is masked.
+const RULE_CREDENTIAL_HEADER: &str = "credential_header";
+
+/// Controls where the masking audit is reported.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum MaskingAuditMode {
+    /// Masked fields are not reported.
+    #[default]
+    None,
+    /// Masked fields are logged at debug level.
+    Log,
+    /// Masked fields are attached to the Treblle payload.
+    Payload,
+}
+
+impl MaskingAuditMode {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "log" => MaskingAuditMode::Log,
+            "payload" => MaskingAuditMode::Payload,
+            _ => MaskingAuditMode::default(),
+        }
+    }
+}
+
+/// Masks sensitive keys in JSON values.
+pub struct Masker {
+    re: Regex,
+    nested_json_max_depth: usize,
+}
+
+impl Masker {
+    /// Creates a new `Masker` for the given sensitive keys pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `sensitive_keys_regex` - The regex pattern to identify sensitive keys.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<Masker>`, or an error if the regex pattern is invalid.
+    pub fn new(sensitive_keys_regex: &str) -> Result<Self> {
+        let re = Regex::new(sensitive_keys_regex).map_err(TreblleError::Regex)?;
+
+        Ok(Masker {
+            re,
+            nested_json_max_depth: 0,
+        })
+    }
+
+    /// Creates a new `Masker` from the middleware configuration.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let masker = Self::new(&config.sensitive_keys_regex)?;
+
+        if config.mask_nested_json {
+            Ok(masker.with_nested_json(config.nested_json_max_depth))
+        } else {
+            Ok(masker)
+        }
+    }
+
+    /// Enables inspection of string values that contain JSON, either as-is or base64-encoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_depth` - How many levels of embedded JSON are parsed; `0` disables inspection.
+    pub fn with_nested_json(mut self, max_depth: usize) -> Self {
+        self.nested_json_max_depth = max_depth;
+        self
+    }
+
+    /// Checks if a key or argument name matches the sensitive keys pattern.
+    pub fn is_sensitive_key(&self, key: &str) -> bool {
+        self.re.is_match(key)
+    }
+
+    /// Masks sensitive keys in a JSON value, recording every masked field.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The JSON value to mask.
+    /// * `root` - The path prefix of `data`, such as `request.body`.
+    /// * `masked_fields` - Receives the path and rule of each masked value, never the value itself.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `Value` with the values of sensitive keys replaced by `*****`.
+    pub fn mask_audited(
+        &self,
+        data: &Value,
+        root: &str,
+        masked_fields: &mut Vec<MaskedField>,
+    ) -> Value {
+        let mut path = root.to_string();
+        self.mask_value(data, &mut path, 0, masked_fields)
+    }
+
+    /// Masks sensitive headers, recording every masked header.
+    ///
+    /// Headers whose name matches the regex are masked entirely. Credential headers
+    /// (`Authorization`, `Proxy-Authorization`, `Cookie`, `Set-Cookie`, `X-Api-Key`) are
+    /// always masked, keeping the auth scheme and cookie names so the shape stays visible.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - A map of headers to mask.
+    /// * `root` - The path prefix of the headers, such as `request.headers`.
+    /// * `masked_fields` - Receives the path and rule of each masked header, never the value itself.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `HashMap<String, String>` containing the masked headers.
+    pub fn mask_headers_audited(
+        &self,
+        headers: &HashMap<String, String>,
+        root: &str,
+        masked_fields: &mut Vec<MaskedField>,
+    ) -> HashMap<String, String> {
+        headers
+            .iter()
+            .map(|(key, value)| {
+                if self.re.is_match(key) {
+                    record(masked_fields, join_path(root, key), RULE_SENSITIVE_KEYS);
+                    (key.clone(), "*****".to_string())
+                } else if is_credential_header(key) {
+                    record(masked_fields, join_path(root, key), RULE_CREDENTIAL_HEADER);
+                    (key.clone(), mask_credential_header(key, value))
+                } else {
+                    (key.clone(), value.clone())
+                }
+            })
+            .collect()
+    }
+
+    fn mask_value(
+        &self,
+        data: &Value,
+        path: &mut String,
+        nested_depth: usize,
+        masked_fields: &mut Vec<MaskedField>,
+    ) -> Value {
+        match data {
+            Value::Object(map) => {
+                let mut new_map = Map::new();
+                for (key, value) in map {
+                    let parent_len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key);
+
+                    if self.re.is_match(key) {
+                        record(masked_fields, path.clone(), RULE_SENSITIVE_KEYS);
+                        new_map.insert(key.clone(), Value::String("*****".to_string()));
+                    } else {
+                        new_map.insert(
+                            key.clone(),
+                            self.mask_value(value, path, nested_depth, masked_fields),
+                        );
+                    }
+
+                    path.truncate(parent_len);
+                }
+                Value::Object(new_map)
+            }
+            Value::Array(arr) => Value::Array(
+                arr.iter()
+                    .enumerate()
+                    .map(|(i, v)| {
+                        let parent_len = path.len();
+                        path.push_str(&format!("[{}]", i));
+                        let masked = self.mask_value(v, path, nested_depth, masked_fields);
+                        path.truncate(parent_len);
+                        masked
+                    })
+                    .collect(),
+            ),
+            Value::String(s) if nested_depth < self.nested_json_max_depth => self
+                .mask_embedded(s, path, nested_depth + 1, masked_fields)
+                .map(Value::String)
+                .unwrap_or_else(|| data.clone()),
+            _ => data.clone(),
+        }
+    }
+
+    /// Masks a string holding JSON or base64-wrapped JSON, re-serializing it the same way.
+    ///
+    /// Returns `None` when the string does not contain embedded JSON.
+    fn mask_embedded(
+        &self,
+        s: &str,
+        path: &mut String,
+        nested_depth: usize,
+        masked_fields: &mut Vec<MaskedField>,
+    ) -> Option<String> {
+        let parent_len = path.len();
+
+        if let Some(embedded) = parse_embedded_json(s.as_bytes()) {
+            path.push_str("<json>");
+            let masked = self.mask_value(&embedded, path, nested_depth, masked_fields);
+            path.truncate(parent_len);
+            return serde_json::to_string(&masked).ok();
+        }
+
+        if s.len() < MIN_BASE64_LEN {
+            return None;
+        }
+
+        [&STANDARD, &URL_SAFE, &URL_SAFE_NO_PAD]
+            .into_iter()
+            .find_map(|engine| {
+                let decoded = engine.decode(s).ok()?;
+                let embedded = parse_embedded_json(&decoded)?;
+
+                path.push_str("<base64-json>");
+                let masked = self.mask_value(&embedded, path, nested_depth, masked_fields);
+                path.truncate(parent_len);
+
+                Some(engine.encode(serde_json::to_vec(&masked).ok()?))
+            })
+    }
+}
+
+impl Default for Masker {
+    /// Creates a `Masker` for the default sensitive keys pattern.
+    fn default() -> Self {
+        Masker::new(DEFAULT_SENSITIVE_KEYS_REGEX)
+            .expect("The default sensitive keys regex is valid")
+    }
+}
+
+fn record(masked_fields: &mut Vec<MaskedField>, path: String, rule: &str) {
+    masked_fields.push(MaskedField {
+        path,
+        rule: rule.to_string(),
+    });
+}
+
+fn join_path(root: &str, key: &str) -> String {
+    if root.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", root, key)
+    }
+}
+
+/// Checks if a header always carries credentials.
+pub(crate) fn is_credential_header(name: &str) -> bool {
+    CREDENTIAL_HEADERS
+        .iter()
+        .any(|header| header.eq_ignore_ascii_case(name))
+}
+
+/// Checks that the raw value of a header may be copied into the payload.
+///
+/// # Arguments
+///
+/// * `config` - The configuration with the sensitive keys pattern and the header allowlist.
+/// * `header` - The name of the header.
+/// * `usage` - What the value is copied into, for the error message.
+///
+/// # Returns
+///
+/// Returns `TreblleError::Config` if the header carries credentials, matches
+/// `sensitiveKeysRegex`, or is left out by a non-empty `headerAllowlist`.
+pub(crate) fn check_reportable_header(config: &Config, header: &str, usage: &str) -> Result<()> {
+    let reason = if is_credential_header(header) {
+        "carries credentials"
+    } else if Masker::from_config(config)
+        .unwrap_or_default()
+        .is_sensitive_key(header)
+    {
+        "matches sensitiveKeysRegex"
+    } else if !config.header_allowlist.is_empty()
+        && !config
+            .header_allowlist
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(header))
+    {
+        "is not in headerAllowlist"
+    } else {
+        return Ok(());
+    };
+
+    Err(TreblleError::Config(format!(
+        "Header {} {} and cannot be used as {}",
+        header, reason, usage
+    )))
+}
+
+/// Masks the value of a credential header while preserving its structure.
+fn mask_credential_header(name: &str, value: &str) -> String {
+    match name.to_ascii_lowercase().as_str() {
+        "authorization" | "proxy-authorization" => mask_authorization(value),
+        "cookie" => mask_cookie(value),
+        "set-cookie" => mask_set_cookie(value),
+        _ => "*****".to_string(),
+    }
+}
+
+/// Masks the credentials of an `Authorization` value, keeping the scheme (`Bearer *****`).
+fn mask_authorization(value: &str) -> String {
+    match value.trim().split_once(char::is_whitespace) {
+        Some((scheme, _)) => format!("{} *****", scheme),
+        None => "*****".to_string(),
+    }
+}
+
+/// Masks every cookie value of a `Cookie` header, keeping the cookie names.
+fn mask_cookie(value: &str) -> String {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(mask_cookie_pair)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Masks the cookie values of a `Set-Cookie` header, keeping the names and attributes.
+///
+/// The host joins repeated headers with NUL bytes, so each cookie is masked separately.
+/// Anything that is not a known cookie attribute is treated as a pair and masked.
+fn mask_set_cookie(value: &str) -> String {
+    value
+        .split(HEADER_VALUE_SEPARATOR)
+        .map(|cookie| {
+            let mut parts = cookie.split(';').map(str::trim);
+            let pair = parts.next().map(mask_cookie_pair).unwrap_or_default();
+
+            std::iter::once(pair)
+                .chain(parts.filter(|attr| !attr.is_empty()).map(|attr| {
+                    if is_cookie_attribute(attr) {
+                        attr.to_string()
+                    } else {
+                        mask_cookie_pair(attr)
+                    }
+                }))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .collect::<Vec<_>>()
+        .join(&HEADER_VALUE_SEPARATOR.to_string())
+}
+
+fn is_cookie_attribute(attr: &str) -> bool {
+    let name = attr.split('=').next().unwrap_or_default().trim();
+
+    SET_COOKIE_ATTRIBUTES
+        .iter()
+        .any(|attribute| attribute.eq_ignore_ascii_case(name))
+}
+
+fn mask_cookie_pair(pair: &str) -> String {
+    match pair.split_once('=') {
+        Some((name, _)) => format!("{}=*****", name.trim()),
+        None => "*****".to_string(),
+    }
+}
+
+/// Parses bytes as JSON only when they hold an object or an array.
+fn parse_embedded_json(bytes: &[u8]) -> Option<Value> {
+    let trimmed = bytes.trim_ascii_start();
+    if !trimmed.starts_with(b"{") && !trimmed.starts_with(b"[") {
+        return None;
+    }
+
+    serde_json::from_slice(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_json_disabled_by_default() -> Result<()> {
+        let masker = Masker::new("password")?;
+        let data = json!({ "payload": "{\"password\":\"x\"}" });
+
+        assert_eq!(
+            masker.mask_audited(&data, "", &mut Vec::new())["payload"],
+            "{\"password\":\"x\"}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_embedded_json_string() -> Result<()> {
+        let masker = Masker::new("password")?.with_nested_json(3);
+        let data = json!({ "payload": "{\"password\":\"x\",\"user\":\"john\"}" });
+
+        let masked = masker.mask_audited(&data, "", &mut Vec::new());
+        let payload: Value = serde_json::from_str(masked["payload"].as_str().unwrap())?;
+
+        assert_eq!(payload["password"], "*****");
+        assert_eq!(payload["user"], "john");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_base64_wrapped_json() -> Result<()> {
+        let masker = Masker::new("password")?.with_nested_json(3);
+        let encoded = STANDARD.encode(r#"{"password":"x"}"#);
+
+        let masked = masker.mask_audited(&json!({ "token": encoded }), "", &mut Vec::new());
+        let decoded = STANDARD.decode(masked["token"].as_str().unwrap()).unwrap();
+        let payload: Value = serde_json::from_slice(&decoded)?;
+
+        assert_eq!(payload["password"], "*****");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_audited_records_paths() -> Result<()> {
+        let masker = Masker::new("password|number")?.with_nested_json(2);
+        let data = json!({
+            "user": { "password": "secret123" },
+            "cards": [{ "number": "4111" }],
+            "payload": "{\"password\":\"x\"}"
+        });
+
+        let mut masked_fields = Vec::new();
+        let masked = masker.mask_audited(&data, "request.body", &mut masked_fields);
+        let paths: Vec<&str> = masked_fields.iter().map(|f| f.path.as_str()).collect();
+
+        assert_eq!(masked["user"]["password"], "*****");
+        assert!(paths.contains(&"request.body.user.password"));
+        assert!(paths.contains(&"request.body.cards[0].number"));
+        assert!(paths.contains(&"request.body.payload<json>.password"));
+        assert!(masked_fields
+            .iter()
+            .all(|f| f.rule == "sensitive_keys_regex"));
+        assert!(!serde_json::to_string(&masked_fields)?.contains("secret123"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_headers_audited() -> Result<()> {
+        let masker = Masker::new("(?i)password")?;
+        let mut headers = HashMap::new();
+        headers.insert("Authorization".to_string(), "Bearer abc".to_string());
+        headers.insert("X-Password".to_string(), "abc".to_string());
+
+        let mut masked_fields = Vec::new();
+        masker.mask_headers_audited(&headers, "request.headers", &mut masked_fields);
+        masked_fields.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(masked_fields[0].path, "request.headers.Authorization");
+        assert_eq!(masked_fields[0].rule, "credential_header");
+        assert_eq!(masked_fields[1].path, "request.headers.X-Password");
+        assert_eq!(masked_fields[1].rule, "sensitive_keys_regex");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_credential_headers() -> Result<()> {
+        let masker = Masker::new("(?i)password")?;
+        let mut headers = HashMap::new();
+        headers.insert("User-Agent".to_string(), "TestAgent".to_string());
+        headers.insert("X-Password".to_string(), "hunter2".to_string());
+        headers.insert("authorization".to_string(), "Bearer token123".to_string());
+        headers.insert("Proxy-Authorization".to_string(), "opaque".to_string());
+        headers.insert("Cookie".to_string(), "session=abc; theme=dark".to_string());
+        headers.insert(
+            "Set-Cookie".to_string(),
+            "session=abc; Path=/; HttpOnly".to_string(),
+        );
+        headers.insert("X-Api-Key".to_string(), "key123".to_string());
+
+        let masked = masker.mask_headers_audited(&headers, "request.headers", &mut Vec::new());
+
+        assert_eq!(masked["User-Agent"], "TestAgent");
+        assert_eq!(masked["X-Password"], "*****");
+        assert_eq!(masked["authorization"], "Bearer *****");
+        assert_eq!(masked["Proxy-Authorization"], "*****");
+        assert_eq!(masked["Cookie"], "session=*****; theme=*****");
+        assert_eq!(masked["Set-Cookie"], "session=*****; Path=/; HttpOnly");
+        assert_eq!(masked["X-Api-Key"], "*****");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(Masker::new(r"[invalid regex").is_err());
+    }
+
+    #[test]
+    fn test_mask_multiple_set_cookies() -> Result<()> {
+        let masker = Masker::new("(?i)password")?;
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Set-Cookie".to_string(),
+            "a=1; Path=/; HttpOnly\0b=2; Path=/; Max-Age=60; tracking=xyz".to_string(),
+        );
+
+        let masked = masker.mask_headers_audited(&headers, "request.headers", &mut Vec::new());
+
+        assert_eq!(
+            masked["Set-Cookie"],
+            "a=*****; Path=/; HttpOnly\0b=*****; Path=/; Max-Age=60; tracking=*****"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_masking_audit_mode_parsing() {
+        assert_eq!(MaskingAuditMode::from_str("LOG"), MaskingAuditMode::Log);
+        assert_eq!(
+            MaskingAuditMode::from_str("payload"),
+            MaskingAuditMode::Payload
+        );
+        assert_eq!(MaskingAuditMode::from_str("other"), MaskingAuditMode::None);
+    }
+
+    #[test]
+    fn test_nested_json_depth_limit() -> Result<()> {
+        let masker = Masker::new("password")?.with_nested_json(1);
+        let inner = json!({ "password": "x" }).to_string();
+        let outer = json!({ "inner": inner }).to_string();
+
+        let masked = masker.mask_audited(&json!({ "outer": outer }), "", &mut Vec::new());
+        let level_one: Value = serde_json::from_str(masked["outer"].as_str().unwrap())?;
+
+        assert_eq!(level_one["inner"], inner);
+
+        Ok(())
+    }
+}
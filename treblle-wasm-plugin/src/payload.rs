@@ -4,14 +4,50 @@
 //! that will be sent to the Treblle API.
 
 use std::collections::HashMap;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use crate::error::Result;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::constants::{
+    ENVIRONMENT_ENV_VARS, REGION_ENV_VARS, SERVER_IP_ENV_VARS, SERVER_NAME_ENV_VARS,
+    SERVER_OS_ENV_VARS, TRAEFIK_VERSION_ENV_VAR,
+};
+use crate::error::{Result, TreblleError};
+use crate::logger::{log, LogLevel};
+use crate::masking::MaskingAuditMode;
 use crate::schema::*;
 use crate::utils;
-use crate::CONFIG;
+use crate::{CLIENT_IP, CONFIG, MASKER, ROUTE_NORMALIZER};
+
+/// The plugin name and version reported in `server.software`.
+const PLUGIN_SOFTWARE: &str = concat!("treblle-wasm-plugin/", env!("CARGO_PKG_VERSION"));
+
+/// Limits applied to request and response bodies before the payload is serialized.
+///
+/// A limit of `0` disables the corresponding check.
+#[derive(Clone, Debug, Default)]
+pub struct PayloadLimits {
+    pub max_string_length: usize,
+    pub max_array_length: usize,
+    pub max_object_depth: usize,
+    pub max_payload_bytes: usize,
+}
+
+impl PayloadLimits {
+    /// Builds the payload limits from the middleware configuration.
+    pub fn from_config(config: &Config) -> Self {
+        PayloadLimits {
+            max_string_length: config.max_string_length,
+            max_array_length: config.max_array_length,
+            max_object_depth: config.max_object_depth,
+            max_payload_bytes: config.max_payload_bytes,
+        }
+    }
+}
 
 /// Represents the payload that will be sent to the Treblle API.
+#[derive(Clone, Debug)]
 pub struct Payload {
     pub data: TrebllePayload,
 }
@@ -37,9 +73,31 @@ impl Payload {
         url: String,
         headers: HashMap<String, String>,
         body: &[u8],
+        source_addr: Option<&str>,
     ) {
-        self.data.data.request = utils::parse_request(method, url, headers, body, &CONFIG)
-            .expect("Error parsing request");
+        let started = Instant::now();
+        let mut masked_fields = Vec::new();
+        let ip = CLIENT_IP.resolve(source_addr, &headers);
+        self.data.data.request = utils::parse_request(
+            method,
+            url,
+            headers,
+            body,
+            ip,
+            &CONFIG,
+            &MASKER,
+            &mut masked_fields,
+        )
+        .expect("Error parsing request");
+
+        // A GraphQL operation route takes precedence over the route template.
+        let request = &mut self.data.data.request;
+        if request.route_path.is_none() {
+            request.route_path = ROUTE_NORMALIZER.route(&request.url);
+        }
+
+        self.record_masked_fields(masked_fields, &CONFIG.masking_audit);
+        self.data.data.timing.masking_ms += millis(started.elapsed());
     }
 
     /// Updates the response information in the payload.
@@ -50,8 +108,68 @@ impl Payload {
         body: &[u8],
         start_time: Instant,
     ) {
-        self.data.data.response = utils::parse_response(status, headers, body, start_time, &CONFIG)
-            .expect("Error parsing response");
+        let started = Instant::now();
+        let mut masked_fields = Vec::new();
+        self.data.data.response = utils::parse_response(
+            status,
+            headers,
+            body,
+            start_time,
+            &CONFIG,
+            &MASKER,
+            &mut masked_fields,
+        )
+        .expect("Error parsing response");
+        self.record_masked_fields(masked_fields, &CONFIG.masking_audit);
+        self.data.data.timing.masking_ms += millis(started.elapsed());
+    }
+
+    /// Updates the timing information in the payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_start` - When `handle_request` was entered.
+    /// * `request_end` - When `handle_request` returned, `None` for events sent while the
+    ///   request is still being handled.
+    /// * `response_start` - When `handle_response` was entered, `None` for request-only events.
+    pub fn update_timing_info(
+        &mut self,
+        request_start: Instant,
+        request_end: Option<Instant>,
+        response_start: Option<Instant>,
+    ) {
+        let timing = &mut self.data.data.timing;
+
+        timing.total_ms = response_start.map(|start| millis(start - request_start));
+        timing.upstream_ms = request_end
+            .zip(response_start)
+            .map(|(end, start)| millis(start.saturating_duration_since(end)));
+        timing.plugin_overhead_ms = match request_end.zip(response_start) {
+            Some((end, start)) => millis(end - request_start) + millis(start.elapsed()),
+            None => millis(request_start.elapsed()),
+        };
+    }
+
+    /// Reports masked fields according to the masking audit mode.
+    ///
+    /// Only paths and rule names are reported; masked values are never included.
+    pub fn record_masked_fields(
+        &mut self,
+        masked_fields: Vec<MaskedField>,
+        mode: &MaskingAuditMode,
+    ) {
+        match mode {
+            MaskingAuditMode::None => {}
+            MaskingAuditMode::Log => {
+                for field in &masked_fields {
+                    log(
+                        LogLevel::Debug,
+                        &format!("Masked {} (rule: {})", field.path, field.rule),
+                    );
+                }
+            }
+            MaskingAuditMode::Payload => self.data.data.masked_fields.extend(masked_fields),
+        }
     }
 
     /// Adds an error to the payload.
@@ -59,30 +177,80 @@ impl Payload {
         self.data.data.errors.push(error);
     }
 
-    /// Converts the payload to a JSON string.
+    /// Converts the payload to a JSON string, applying the configured payload limits.
     pub fn to_json(&self) -> Result<String> {
-        serde_json::to_string(&self.data).map_err(Into::into)
+        self.to_json_with_limits(&PayloadLimits::from_config(&CONFIG))
+    }
+
+    /// Converts the payload to a JSON string that respects the given limits.
+    ///
+    /// Bodies are shaped first (long strings truncated, long arrays cut, deep objects
+    /// collapsed). If the result still exceeds `max_payload_bytes`, the response body and
+    /// then the request body are dropped. A payload that cannot be brought under the
+    /// ceiling is rejected with `TreblleError::PayloadTooLarge`.
+    pub fn to_json_with_limits(&self, limits: &PayloadLimits) -> Result<String> {
+        let started = Instant::now();
+        let mut data = self.data.clone();
+        data.data.request.body = data.data.request.body.map(|b| shape_value(&b, limits, 0));
+        data.data.response.body = data.data.response.body.map(|b| shape_value(&b, limits, 0));
+        data.data.timing.shaping_ms = millis(started.elapsed());
+
+        let mut json = serde_json::to_string(&data)?;
+        if limits.max_payload_bytes == 0 || json.len() <= limits.max_payload_bytes {
+            return Ok(json);
+        }
+
+        for drop_request_body in [false, true] {
+            let original_len = json.len();
+            let body = if drop_request_body {
+                &mut data.data.request.body
+            } else {
+                &mut data.data.response.body
+            };
+
+            if body.is_none() {
+                continue;
+            }
+            *body = Some(Value::String(format!(
+                "[body removed: payload exceeded {} bytes]",
+                limits.max_payload_bytes
+            )));
+
+            json = serde_json::to_string(&data)?;
+            log(
+                LogLevel::Debug,
+                &format!(
+                    "Dropped {} body to shrink payload from {} to {} bytes",
+                    if drop_request_body {
+                        "request"
+                    } else {
+                        "response"
+                    },
+                    original_len,
+                    json.len()
+                ),
+            );
+
+            if json.len() <= limits.max_payload_bytes {
+                return Ok(json);
+            }
+        }
+
+        Err(TreblleError::PayloadTooLarge(json.len()))
     }
 
     /// Updates the server information in the payload.
-    /// Although this function exists here for proper & complete schema validated response,
-    /// it's not going to see much use within WASM environment. 
-    /// 
-    /// This part of payload schema is intended for SDK/middleware use.
-    pub fn update_server_info(&mut self, protocol: String) {
-        self.data.data.server = ServerInfo {
-            ip: "Unknown".to_string(), // TODO: Implement server IP detection
-            timezone: chrono::Local::now().format("%Z").to_string(),
-            software: None,
-            signature: None,
-            protocol,
-            encoding: None,
-            os: OsInfo {
-                name: std::env::consts::OS.to_string(),
-                release: "Unknown".to_string(), // TODO: Implement OS release detection
-                architecture: std::env::consts::ARCH.to_string(),
-            },
-        };
+    ///
+    /// The guest cannot inspect the host, so the server identity comes from the
+    /// configuration, falling back to environment variables passed to the WASI module.
+    ///
+    /// # Arguments
+    ///
+    /// * `protocol` - The HTTP protocol version of the request.
+    /// * `encoding` - The charset of the response, if known.
+    pub fn update_server_info(&mut self, protocol: String, encoding: Option<String>) {
+        self.data.data.server =
+            server_info(&CONFIG, protocol, encoding, |name| std::env::var(name).ok());
     }
 
     /// Updates the language information in the payload.
@@ -96,7 +264,128 @@ impl Payload {
     }
 }
 
+/// Applies the string, array and depth limits to a JSON value.
+///
+/// # Arguments
+///
+/// * `value` - The JSON value to shape.
+/// * `limits` - The limits to apply.
+/// * `depth` - The nesting depth of `value`.
+///
+/// # Returns
+///
+/// Returns a new `Value` within the given limits.
+pub fn shape_value(value: &Value, limits: &PayloadLimits, depth: usize) -> Value {
+    if limits.max_object_depth > 0 && depth >= limits.max_object_depth {
+        let collapsed = match value {
+            Value::Array(arr) => Some(("array", arr.len())),
+            Value::Object(map) => Some(("object", map.len())),
+            _ => None,
+        };
+
+        if let Some((kind, len)) = collapsed {
+            return Value::String(format!(
+                "[{} with {} entries: max depth exceeded]",
+                kind, len
+            ));
+        }
+    }
+
+    match value {
+        Value::String(s) => match s.char_indices().nth(limits.max_string_length) {
+            Some((cut, _)) if limits.max_string_length > 0 => Value::String(format!(
+                "{}...[{} more chars]",
+                &s[..cut],
+                s[cut..].chars().count()
+            )),
+            _ => value.clone(),
+        },
+        Value::Array(arr) => {
+            let keep = if limits.max_array_length > 0 {
+                arr.len().min(limits.max_array_length)
+            } else {
+                arr.len()
+            };
+
+            let mut shaped: Vec<Value> = arr[..keep]
+                .iter()
+                .map(|v| shape_value(v, limits, depth + 1))
+                .collect();
+
+            if keep < arr.len() {
+                shaped.push(Value::String(format!(
+                    "...[{} more items]",
+                    arr.len() - keep
+                )));
+            }
+
+            Value::Array(shaped)
+        }
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), shape_value(v, limits, depth + 1)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+/// Converts a duration to fractional milliseconds.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Builds the server information.
+///
+/// # Arguments
+///
+/// * `config` - The configuration holding the server identity.
+/// * `protocol` - The HTTP protocol version of the request.
+/// * `encoding` - The charset of the response, if known.
+/// * `env` - Looks up an environment variable.
+fn server_info(
+    config: &Config,
+    protocol: String,
+    encoding: Option<String>,
+    env: impl Fn(&str) -> Option<String>,
+) -> ServerInfo {
+    let setting = |configured: &Option<String>, names: &[&str]| {
+        configured.clone().or_else(|| {
+            names
+                .iter()
+                .filter_map(|name| env(name))
+                .find(|value| !value.trim().is_empty())
+        })
+    };
+
+    let software = match env(TRAEFIK_VERSION_ENV_VAR).filter(|v| !v.trim().is_empty()) {
+        Some(version) => format!("Traefik/{} {}", version, PLUGIN_SOFTWARE),
+        None => format!("Traefik {}", PLUGIN_SOFTWARE),
+    };
+
+    ServerInfo {
+        ip: setting(&config.server_ip, &SERVER_IP_ENV_VARS)
+            .unwrap_or_else(|| "Unknown".to_string()),
+        timezone: chrono::Local::now().format("%Z").to_string(),
+        software: Some(software),
+        signature: None,
+        protocol,
+        encoding,
+        os: OsInfo {
+            // `std::env::consts::OS` is the guest target (`wasi`), not the host OS.
+            name: setting(&config.server_os, &SERVER_OS_ENV_VARS)
+                .unwrap_or_else(|| "Unknown".to_string()),
+            release: "Unknown".to_string(), // The WASI sandbox does not expose the host OS release
+            architecture: std::env::consts::ARCH.to_string(),
+        },
+        name: setting(&config.server_name, &SERVER_NAME_ENV_VARS),
+        environment: setting(&config.environment, &ENVIRONMENT_ENV_VARS),
+        region: setting(&config.region, &REGION_ENV_VARS),
+    }
+}
+
 /// Checks if the given content type is JSON.
+#[allow(dead_code)]
 pub fn is_json(content_type: &str) -> bool {
     utils::is_json(content_type)
 }
@@ -118,6 +407,7 @@ mod tests {
             buffer_response: false,
             log_level: LogLevel::None,
             root_ca_path: None,
+            ..Config::fallback()
         }
     }
 
@@ -160,10 +450,171 @@ mod tests {
         let headers = HashMap::new();
         let body = b"test body";
 
-        payload.update_request_info(method.clone(), url.clone(), headers, body);
+        payload.update_request_info(method.clone(), url.clone(), headers, body, None);
 
         assert_eq!(payload.data.data.request.method, method);
         assert_eq!(payload.data.data.request.url, url);
+        assert_eq!(payload.data.data.request.route_path, None);
+
+        payload.update_request_info(method, "/orders/42".to_string(), HashMap::new(), b"", None);
+
+        assert_eq!(
+            payload.data.data.request.route_path,
+            Some("/orders/{id}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_server_info() {
+        let config = Config {
+            server_name: Some("edge-fra-1".to_string()),
+            ..create_test_config()
+        };
+        let env = |name: &str| match name {
+            "POD_IP" => Some("10.1.2.3".to_string()),
+            "HOSTNAME" => Some("traefik-7d9f".to_string()),
+            "AWS_REGION" => Some("eu-central-1".to_string()),
+            "TRAEFIK_VERSION" => Some("3.1.2".to_string()),
+            "OSTYPE" => Some("linux-gnu".to_string()),
+            _ => None,
+        };
+
+        let server = server_info(
+            &config,
+            "HTTP/1.1".to_string(),
+            Some("utf-8".to_string()),
+            env,
+        );
+
+        assert_eq!(server.ip, "10.1.2.3");
+        assert_eq!(server.name, Some("edge-fra-1".to_string()));
+        assert_eq!(server.environment, None);
+        assert_eq!(server.region, Some("eu-central-1".to_string()));
+        assert_eq!(
+            server.software,
+            Some(format!("Traefik/3.1.2 {}", PLUGIN_SOFTWARE))
+        );
+        assert_eq!(server.encoding, Some("utf-8".to_string()));
+        assert_eq!(server.os.name, "linux-gnu");
+
+        let server = server_info(&config, "HTTP/2.0".to_string(), None, |_| None);
+
+        assert_eq!(server.ip, "Unknown");
+        assert_eq!(server.os.name, "Unknown");
+        assert_eq!(
+            server.software,
+            Some(format!("Traefik {}", PLUGIN_SOFTWARE))
+        );
+    }
+
+    #[test]
+    fn test_update_timing_info() {
+        let mut payload = create_test_payload();
+        let request_start = Instant::now();
+        let request_end = request_start + Duration::from_millis(5);
+        let response_start = request_start + Duration::from_millis(105);
+
+        payload.update_timing_info(request_start, Some(request_end), Some(response_start));
+        let timing = &payload.data.data.timing;
+
+        assert_eq!(timing.total_ms, Some(105.0));
+        assert_eq!(timing.upstream_ms, Some(100.0));
+        assert!(timing.plugin_overhead_ms >= 5.0 && timing.plugin_overhead_ms < 100.0);
+
+        payload.update_timing_info(request_start, None, None);
+
+        assert_eq!(payload.data.data.timing.total_ms, None);
+        assert_eq!(payload.data.data.timing.upstream_ms, None);
+    }
+
+    fn create_test_payload() -> Payload {
+        let config = create_test_config();
+
+        Payload {
+            data: TrebllePayload {
+                api_key: config.api_key.clone(),
+                project_id: config.project_id.clone(),
+                version: 0.6,
+                sdk: "rust-wasm".to_string(),
+                data: PayloadData::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_shape_value() {
+        let limits = PayloadLimits {
+            max_string_length: 5,
+            max_array_length: 2,
+            max_object_depth: 2,
+            max_payload_bytes: 0,
+        };
+        let value = serde_json::json!({
+            "name": "abcdefgh",
+            "items": [1, 2, 3, 4],
+            "nested": { "deep": { "deeper": true } }
+        });
+
+        let shaped = shape_value(&value, &limits, 0);
+
+        assert_eq!(shaped["name"], "abcde...[3 more chars]");
+        assert_eq!(
+            shaped["items"],
+            serde_json::json!([1, 2, "...[2 more items]"])
+        );
+        assert_eq!(
+            shaped["nested"]["deep"],
+            "[object with 1 entries: max depth exceeded]"
+        );
+    }
+
+    #[test]
+    fn test_to_json_with_limits_drops_bodies() -> Result<()> {
+        let mut payload = create_test_payload();
+        payload.data.data.request.body = Some(serde_json::json!({ "small": true }));
+        payload.data.data.response.body = Some(Value::String("x".repeat(10_000)));
+
+        let unlimited = payload.to_json_with_limits(&PayloadLimits::default())?;
+        let limits = PayloadLimits {
+            max_payload_bytes: 2_000,
+            ..PayloadLimits::default()
+        };
+        let limited = payload.to_json_with_limits(&limits)?;
+
+        assert!(unlimited.len() > 10_000);
+        assert!(limited.len() <= 2_000);
+        assert!(limited.contains("\"small\":true"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_json_with_limits_rejects_oversized_payload() {
+        let payload = create_test_payload();
+        let limits = PayloadLimits {
+            max_payload_bytes: 10,
+            ..PayloadLimits::default()
+        };
+
+        assert!(matches!(
+            payload.to_json_with_limits(&limits),
+            Err(TreblleError::PayloadTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_record_masked_fields() {
+        let mut payload = create_test_payload();
+        let field = MaskedField {
+            path: "request.body.password".to_string(),
+            rule: "sensitive_keys_regex".to_string(),
+        };
+
+        payload.record_masked_fields(vec![field.clone()], &MaskingAuditMode::None);
+        assert!(payload.data.data.masked_fields.is_empty());
+
+        payload.record_masked_fields(vec![field.clone()], &MaskingAuditMode::Payload);
+        assert_eq!(payload.data.data.masked_fields, vec![field]);
     }
 
     #[test]
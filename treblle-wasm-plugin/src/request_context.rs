@@ -0,0 +1,237 @@
+//! Request context module for the Treblle middleware.
+//!
+//! This module keeps per-request state between `handle_request` and `handle_response`.
+//! The context ID is returned to the host in the high 32 bits of `handle_request`'s
+//! result and handed back as `req_ctx` to `handle_response`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+
+use crate::logger::{log, LogLevel};
+use crate::payload::Payload;
+use crate::schema::{JsonRpcCall, TraceInfo, UserInfo};
+
+/// Maximum number of contexts kept while waiting for their response.
+const MAX_PENDING_CONTEXTS: usize = 10_000;
+
+static NEXT_CONTEXT_ID: AtomicI32 = AtomicI32::new(1);
+
+static CONTEXTS: Lazy<Mutex<PendingContexts>> =
+    Lazy::new(|| Mutex::new(PendingContexts::default()));
+
+/// Contexts waiting for their response, evicted oldest first when full.
+#[derive(Debug, Default)]
+struct PendingContexts {
+    contexts: HashMap<i32, (u64, RequestContext)>,
+    /// Context IDs by insertion order; IDs wrap around, so they cannot order themselves.
+    order: BTreeMap<u64, i32>,
+    next_seq: u64,
+}
+
+impl PendingContexts {
+    /// Stores a context, evicting the oldest one first if `max` contexts are pending.
+    ///
+    /// # Returns
+    ///
+    /// Returns the ID of the evicted context, if any.
+    fn insert(&mut self, id: i32, context: RequestContext, max: usize) -> Option<i32> {
+        let evicted = if self.contexts.len() >= max {
+            self.order.pop_first().map(|(_, oldest)| {
+                self.contexts.remove(&oldest);
+                oldest
+            })
+        } else {
+            None
+        };
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some((old_seq, _)) = self.contexts.insert(id, (seq, context)) {
+            self.order.remove(&old_seq);
+        }
+        self.order.insert(seq, id);
+
+        evicted
+    }
+
+    fn remove(&mut self, id: i32) -> Option<RequestContext> {
+        let (seq, context) = self.contexts.remove(&id)?;
+        self.order.remove(&seq);
+        Some(context)
+    }
+}
+
+/// State carried from the request phase to the response phase.
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+    /// The request was selected by the route filter and the sampler.
+    pub sampled: bool,
+    /// The response should be captured if it fails, even when the request was not sampled.
+    pub capture_errors: bool,
+    /// Response buffering is enabled for the route, so the body can be read if captured.
+    pub buffer_response: bool,
+    /// When `handle_request` was entered.
+    pub start_time: Option<Instant>,
+    /// Wall-clock time matching `start_time`, reported as the request timestamp.
+    pub received_at: Option<DateTime<Utc>>,
+    /// When `handle_request` returned and the request was handed to the next handler.
+    pub forwarded_at: Option<Instant>,
+    /// Request data held back until the response decides whether it is shipped.
+    pub pending_payload: Option<Payload>,
+    /// The correlation ID of the request.
+    pub request_id: Option<String>,
+    /// The distributed trace the request belongs to.
+    pub trace: Option<TraceInfo>,
+    /// Tags attached to the events of the request.
+    pub tags: HashMap<String, String>,
+    /// The caller of the request.
+    pub user: Option<UserInfo>,
+    /// JSON-RPC calls made by the request, used to correlate response errors.
+    pub jsonrpc_calls: Vec<JsonRpcCall>,
+}
+
+impl RequestContext {
+    /// Checks if the response of this request should be captured.
+    ///
+    /// # Arguments
+    ///
+    /// * `failed` - Whether the response is an error (status >= 400 or reported by Traefik)
+    pub fn should_capture_response(&self, failed: bool) -> bool {
+        self.sampled || (self.capture_errors && failed)
+    }
+
+    /// Checks if Traefik should buffer the response.
+    ///
+    /// Buffering holds the whole response in memory, so it is only enabled when the
+    /// response may be captured; unsampled requests are forwarded untouched.
+    pub fn should_buffer_response(&self) -> bool {
+        self.buffer_response && (self.sampled || self.capture_errors)
+    }
+}
+
+/// Stores a context and returns the ID to hand to the host.
+///
+/// Returns `0` if the context could not be stored; `take(0)` always yields `None`.
+pub fn store(context: RequestContext) -> i32 {
+    let mut contexts = match CONTEXTS.lock() {
+        Ok(guard) => guard,
+        Err(e) => {
+            log(
+                LogLevel::Error,
+                &format!("Failed to acquire request context lock: {}", e),
+            );
+            return 0;
+        }
+    };
+
+    // Skip 0 on wrap-around, it is reserved for "no context".
+    let mut id = NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed);
+    if id == 0 {
+        id = NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if let Some(evicted) = contexts.insert(id, context, MAX_PENDING_CONTEXTS) {
+        log(
+            LogLevel::Warn,
+            &format!(
+                "Too many pending request contexts, dropping the oldest one ({})",
+                evicted
+            ),
+        );
+    }
+
+    id
+}
+
+/// Removes and returns the context stored under `id`.
+pub fn take(id: i32) -> Option<RequestContext> {
+    if id == 0 {
+        return None;
+    }
+
+    CONTEXTS.lock().ok()?.remove(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_take() {
+        let first = store(RequestContext {
+            sampled: true,
+            ..Default::default()
+        });
+        let second = store(RequestContext::default());
+
+        assert_ne!(first, second);
+        assert!(take(first).unwrap().sampled);
+        assert!(take(first).is_none());
+        assert!(!take(second).unwrap().sampled);
+    }
+
+    #[test]
+    fn test_pending_contexts_evict_oldest() {
+        let mut pending = PendingContexts::default();
+        let context = |sampled| RequestContext {
+            sampled,
+            ..Default::default()
+        };
+
+        assert_eq!(pending.insert(7, context(false), 2), None);
+        assert_eq!(pending.insert(3, context(true), 2), None);
+        assert_eq!(pending.insert(5, context(false), 2), Some(7));
+
+        assert!(pending.remove(7).is_none());
+        assert!(pending.remove(3).unwrap().sampled);
+        assert_eq!(pending.insert(9, context(false), 2), None);
+        assert_eq!(pending.insert(1, context(false), 2), Some(5));
+        assert_eq!(pending.contexts.len(), pending.order.len());
+    }
+
+    #[test]
+    fn test_should_capture_response() {
+        let sampled = RequestContext {
+            sampled: true,
+            ..Default::default()
+        };
+        let errors_only = RequestContext {
+            capture_errors: true,
+            ..Default::default()
+        };
+
+        assert!(sampled.should_capture_response(false));
+        assert!(errors_only.should_capture_response(true));
+        assert!(!errors_only.should_capture_response(false));
+        assert!(!RequestContext::default().should_capture_response(true));
+    }
+
+    #[test]
+    fn test_should_buffer_response() {
+        let context = |sampled, capture_errors| RequestContext {
+            sampled,
+            capture_errors,
+            buffer_response: true,
+            ..Default::default()
+        };
+
+        assert!(context(true, false).should_buffer_response());
+        assert!(context(false, true).should_buffer_response());
+        assert!(!context(false, false).should_buffer_response());
+        assert!(!RequestContext {
+            sampled: true,
+            ..Default::default()
+        }
+        .should_buffer_response());
+    }
+
+    #[test]
+    fn test_take_without_context() {
+        assert!(take(0).is_none());
+    }
+}
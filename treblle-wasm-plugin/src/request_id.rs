@@ -0,0 +1,129 @@
+//! Request ID module for the Treblle middleware.
+//!
+//! This module reads the correlation ID of a request from the configured header, or
+//! generates a UUIDv4 for requests that have none, so customer reports can be linked to
+//! Treblle events.
+
+use std::collections::HashMap;
+
+use crate::error::Result;
+use crate::utils::{get_header_value, random_bytes, to_hex};
+
+/// Longest incoming ID that is accepted, to keep oversized values out of the payload.
+const MAX_REQUEST_ID_LENGTH: usize = 200;
+
+/// Reads the request ID sent by the client.
+///
+/// # Arguments
+///
+/// * `headers` - The request headers.
+/// * `header` - The name of the request ID header.
+///
+/// # Returns
+///
+/// Returns the ID, or `None` if the header is missing, empty, too long or contains
+/// characters other than visible ASCII.
+pub fn from_headers(headers: &HashMap<String, String>, header: &str) -> Option<String> {
+    get_header_value(headers, header)
+        .map(|id| id.trim().to_string())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+}
+
+/// Resolves the request ID, keeping the one sent by the client.
+///
+/// # Arguments
+///
+/// * `headers` - The request headers.
+/// * `header` - The name of the request ID header.
+///
+/// # Returns
+///
+/// Returns the ID and whether it was generated, or `TreblleError::HostFunction` if the
+/// request has no ID and the host provides no randomness.
+pub fn resolve(headers: &HashMap<String, String>, header: &str) -> Result<(String, bool)> {
+    match from_headers(headers, header) {
+        Some(id) => Ok((id, false)),
+        None => Ok((generate()?, true)),
+    }
+}
+
+/// Generates a random UUIDv4, e.g. `0b6c9f0e-3d1a-4f8e-9c2b-7a5d4e3f2a1b`.
+///
+/// # Returns
+///
+/// Returns the UUID, or `TreblleError::HostFunction` if the host provides no randomness.
+pub fn generate() -> Result<String> {
+    let mut bytes = random_bytes(16)?;
+
+    // Set the version (4) and the RFC 4122 variant bits.
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = to_hex(&bytes);
+
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HashMap::new();
+        assert_eq!(from_headers(&headers, "X-Request-Id"), None);
+
+        headers.insert("x-request-id".to_string(), " abc-123 ".to_string());
+        assert_eq!(
+            from_headers(&headers, "X-Request-Id"),
+            Some("abc-123".to_string())
+        );
+
+        headers.insert("x-request-id".to_string(), "has spaces".to_string());
+        assert_eq!(from_headers(&headers, "X-Request-Id"), None);
+
+        headers.insert("x-request-id".to_string(), "a".repeat(201));
+        assert_eq!(from_headers(&headers, "X-Request-Id"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let headers = HashMap::from([("X-Request-Id".to_string(), "abc-123".to_string())]);
+        assert_eq!(
+            resolve(&headers, "x-request-id").unwrap(),
+            ("abc-123".to_string(), false)
+        );
+
+        let (id, generated) = resolve(&HashMap::new(), "X-Request-Id").unwrap();
+        assert!(generated);
+        assert_eq!(id.len(), 36);
+    }
+
+    #[test]
+    fn test_generate() {
+        let id = generate().unwrap();
+        let parts: Vec<&str> = id.split('-').collect();
+
+        assert_eq!(
+            parts.iter().map(|p| p.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(parts[2].starts_with('4'));
+        assert!(matches!(
+            parts[3].chars().next(),
+            Some('8' | '9' | 'a' | 'b')
+        ));
+        assert_ne!(id, generate().unwrap());
+    }
+}
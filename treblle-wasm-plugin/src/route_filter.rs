@@ -0,0 +1,532 @@
+//! Route filter module for the Treblle middleware.
+//!
+//! This module decides which requests are captured, based on blacklist and
+//! whitelist rules that match on method, host, path, query and headers.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::error::{Result, TreblleError};
+
+/// Decides which list wins when a request matches both the blacklist and the whitelist.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum RoutePrecedence {
+    /// A blacklisted request is never captured, even if it is whitelisted.
+    #[default]
+    Blacklist,
+    /// A whitelisted request is always captured, even if it is blacklisted.
+    Whitelist,
+}
+
+impl RoutePrecedence {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "whitelist" | "allowlist" => RoutePrecedence::Whitelist,
+            _ => RoutePrecedence::default(),
+        }
+    }
+}
+
+/// Represents a route rule as written in the configuration.
+///
+/// A plain string is a regex matched against the full URI, which keeps
+/// `routeBlacklist: ["/health"]` working. Objects combine any of the conditions below,
+/// and all of them must match for the rule to apply.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RouteRuleConfig {
+    /// HTTP methods, compared case-insensitively. Empty matches any method.
+    pub methods: Vec<String>,
+    /// Host glob, e.g. `*.example.com`.
+    pub host: Option<String>,
+    /// Path glob, e.g. `/api/**/internal`.
+    pub path: Option<String>,
+    /// Regex matched against the full URI, including the query string.
+    pub uri: Option<String>,
+    /// Query parameters that must be present.
+    pub query: Vec<String>,
+    /// Headers that must be present, mapped to a value glob (`*` accepts any value).
+    pub headers: HashMap<String, String>,
+}
+
+impl RouteRuleConfig {
+    /// Keys a rule object may contain.
+    const KEYS: [&'static str; 7] = [
+        "method", "methods", "host", "path", "uri", "query", "headers",
+    ];
+
+    /// Reads a rule from a configuration value, either a URI regex string or a rule object.
+    ///
+    /// # Returns
+    ///
+    /// Returns the rule, or `TreblleError::Config` if it has unknown keys, wrongly typed
+    /// values or no condition at all, since an empty rule would match every request.
+    pub fn from_value(value: &Value) -> Result<Self> {
+        Self::from_value_with_keys(value, &[])
+    }
+
+    /// Reads a rule whose object may also hold `extra_keys`, such as the `rate` of a
+    /// per-route sample rate.
+    pub(crate) fn from_value_with_keys(value: &Value, extra_keys: &[&str]) -> Result<Self> {
+        let invalid = |reason: String| {
+            TreblleError::Config(format!("Invalid route rule {}: {}", value, reason))
+        };
+
+        if let Some(pattern) = value.as_str() {
+            if pattern.is_empty() {
+                return Err(invalid("the pattern is empty".to_string()));
+            }
+
+            return Ok(RouteRuleConfig {
+                uri: Some(pattern.to_string()),
+                ..Default::default()
+            });
+        }
+
+        let rule = value
+            .as_object()
+            .ok_or_else(|| invalid("expected a string or an object".to_string()))?;
+
+        if let Some(key) = rule
+            .keys()
+            .find(|key| !Self::KEYS.contains(&key.as_str()) && !extra_keys.contains(&key.as_str()))
+        {
+            return Err(invalid(format!("unknown key {}", key)));
+        }
+
+        let string = |key: &str| -> Result<Option<String>> {
+            match rule.get(key) {
+                None => Ok(None),
+                Some(Value::String(s)) => Ok(Some(s.clone())),
+                Some(_) => Err(invalid(format!("{} must be a string", key))),
+            }
+        };
+        let strings = |key: &str, value: &Value| -> Result<Vec<String>> {
+            match value {
+                Value::String(s) => Ok(vec![s.clone()]),
+                Value::Array(items) => items
+                    .iter()
+                    .map(|v| {
+                        v.as_str()
+                            .map(String::from)
+                            .ok_or_else(|| invalid(format!("{} must only hold strings", key)))
+                    })
+                    .collect(),
+                _ => Err(invalid(format!(
+                    "{} must be a string or a list of strings",
+                    key
+                ))),
+            }
+        };
+
+        let mut methods = Vec::new();
+        for key in ["method", "methods"] {
+            if let Some(value) = rule.get(key) {
+                methods.extend(strings(key, value)?);
+            }
+        }
+
+        let config = RouteRuleConfig {
+            methods,
+            host: string("host")?,
+            path: string("path")?,
+            uri: string("uri")?,
+            query: rule
+                .get("query")
+                .map(|v| strings("query", v))
+                .transpose()?
+                .unwrap_or_default(),
+            headers: match rule.get("headers") {
+                None => HashMap::new(),
+                Some(Value::Object(headers)) => headers
+                    .iter()
+                    .map(|(name, v)| match v {
+                        Value::String(glob) => Ok((name.clone(), glob.clone())),
+                        _ => Err(invalid(format!("header {} must map to a string", name))),
+                    })
+                    .collect::<Result<_>>()?,
+                Some(_) => Err(invalid("headers must be an object".to_string()))?,
+            },
+        };
+
+        if config == RouteRuleConfig::default() {
+            return Err(invalid("the rule has no condition".to_string()));
+        }
+
+        Ok(config)
+    }
+}
+
+/// The parts of a request that route rules match against.
+pub struct RouteRequest<'a> {
+    pub method: &'a str,
+    pub host: &'a str,
+    pub uri: &'a str,
+    pub headers: &'a HashMap<String, String>,
+}
+
+/// A compiled route rule.
+#[derive(Clone, Debug)]
+pub(crate) struct RouteRule {
+    methods: Vec<String>,
+    host: Option<Regex>,
+    path: Option<Regex>,
+    uri: Option<Regex>,
+    query: Vec<String>,
+    headers: Vec<(String, Regex)>,
+}
+
+impl RouteRule {
+    pub(crate) fn compile(config: &RouteRuleConfig) -> Result<Self> {
+        let invalid = |e: regex::Error| {
+            TreblleError::Config(format!("Invalid route rule {:?}: {}", config, e))
+        };
+
+        Ok(RouteRule {
+            methods: config.methods.iter().map(|m| m.to_uppercase()).collect(),
+            host: config
+                .host
+                .as_deref()
+                .map(|host| Regex::new(&format!("(?i){}", glob_to_regex(host, Some('.')))))
+                .transpose()
+                .map_err(invalid)?,
+            path: config
+                .path
+                .as_deref()
+                .map(|path| Regex::new(&glob_to_regex(path, Some('/'))))
+                .transpose()
+                .map_err(invalid)?,
+            uri: config
+                .uri
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(invalid)?,
+            query: config.query.clone(),
+            headers: config
+                .headers
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), Regex::new(&glob_to_regex(value, None))?)))
+                .collect::<std::result::Result<_, regex::Error>>()
+                .map_err(invalid)?,
+        })
+    }
+
+    pub(crate) fn matches(&self, request: &RouteRequest) -> bool {
+        let (path, query) = match request.uri.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (request.uri, ""),
+        };
+
+        (self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(request.method)))
+            && self
+                .host
+                .as_ref()
+                .map_or(true, |re| re.is_match(strip_port(request.host)))
+            && self.path.as_ref().map_or(true, |re| re.is_match(path))
+            && self
+                .uri
+                .as_ref()
+                .map_or(true, |re| re.is_match(request.uri))
+            && self.query.iter().all(|param| has_query_param(query, param))
+            && self.headers.iter().all(|(name, value)| {
+                request
+                    .headers
+                    .iter()
+                    .any(|(k, v)| k.eq_ignore_ascii_case(name) && value.is_match(v))
+            })
+    }
+}
+
+/// Decides whether a request should be captured.
+#[derive(Clone, Debug, Default)]
+pub struct RouteFilter {
+    blacklist: Vec<RouteRule>,
+    whitelist: Vec<RouteRule>,
+    precedence: RoutePrecedence,
+    /// Set when the rules could not be read, so that no request is captured.
+    capture_none: bool,
+}
+
+impl RouteFilter {
+    /// Creates a new `RouteFilter` instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `blacklist` - Rules for requests that must not be captured.
+    /// * `whitelist` - Rules for requests that may be captured; empty allows every request.
+    /// * `precedence` - Which list wins when a request matches both.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<RouteFilter>`, or `TreblleError::Config` if a rule is invalid.
+    pub fn new(
+        blacklist: &[RouteRuleConfig],
+        whitelist: &[RouteRuleConfig],
+        precedence: RoutePrecedence,
+    ) -> Result<Self> {
+        Ok(RouteFilter {
+            blacklist: blacklist
+                .iter()
+                .map(RouteRule::compile)
+                .collect::<Result<_>>()?,
+            whitelist: whitelist
+                .iter()
+                .map(RouteRule::compile)
+                .collect::<Result<_>>()?,
+            precedence,
+            capture_none: false,
+        })
+    }
+
+    /// Creates a new `RouteFilter` from the middleware configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<RouteFilter>`, or `TreblleError::Config` if a rule is invalid or
+    /// a malformed rule was dropped while reading the configuration.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        if config.invalid_route_rules {
+            return Err(TreblleError::Config(
+                "Some route rules could not be read".to_string(),
+            ));
+        }
+
+        Self::new(
+            &config.route_blacklist,
+            &config.route_whitelist,
+            config.route_precedence.clone(),
+        )
+    }
+
+    /// Creates a `RouteFilter` that captures no request, used when the rules are invalid
+    /// so that blacklisted routes are never captured by mistake.
+    pub fn capture_none() -> Self {
+        RouteFilter {
+            capture_none: true,
+            ..Default::default()
+        }
+    }
+
+    /// Checks if a request should be captured.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to check against the rules.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the request passes the whitelist and blacklist, `false` otherwise.
+    pub fn should_capture(&self, request: &RouteRequest) -> bool {
+        if self.capture_none {
+            return false;
+        }
+
+        let whitelisted = self.whitelist.iter().any(|rule| rule.matches(request));
+        let blacklisted = self.blacklist.iter().any(|rule| rule.matches(request));
+
+        match self.precedence {
+            RoutePrecedence::Blacklist => {
+                (self.whitelist.is_empty() || whitelisted) && !blacklisted
+            }
+            RoutePrecedence::Whitelist => {
+                whitelisted || (self.whitelist.is_empty() && !blacklisted)
+            }
+        }
+    }
+}
+
+/// Converts a glob into an anchored regex.
+///
+/// `**` matches anything, `*` and `?` stop at `separator` when one is given. A `/**/`
+/// segment also matches a single `/`, so `/api/**/internal` matches `/api/internal`.
+fn glob_to_regex(glob: &str, separator: Option<char>) -> String {
+    let not_separator = match separator {
+        Some(separator) => format!("[^{}]", regex::escape(&separator.to_string())),
+        None => ".".to_string(),
+    };
+    let mut regex = String::from("^");
+    let mut rest = glob;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("/**/") {
+            regex.push_str("(?:/.*)?/");
+            rest = &rest[4..];
+        } else if rest.starts_with("**") {
+            regex.push_str(".*");
+            rest = &rest[2..];
+        } else {
+            match c {
+                '*' => regex.push_str(&format!("{}*", not_separator)),
+                '?' => regex.push_str(&not_separator),
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+fn has_query_param(query: &str, param: &str) -> bool {
+    url::form_urlencoded::parse(query.as_bytes()).any(|(key, _)| key == param)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request<'a>(
+        method: &'a str,
+        uri: &'a str,
+        headers: &'a HashMap<String, String>,
+    ) -> RouteRequest<'a> {
+        RouteRequest {
+            method,
+            host: "api.example.com:443",
+            uri,
+            headers,
+        }
+    }
+
+    fn rules(values: Value) -> Vec<RouteRuleConfig> {
+        values
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| RouteRuleConfig::from_value(value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_route_blacklist() {
+        let filter = RouteFilter::new(
+            &rules(json!([r"^/api/internal/.*$", r"^/health$"])),
+            &[],
+            RoutePrecedence::Blacklist,
+        )
+        .unwrap();
+        let headers = HashMap::new();
+
+        assert!(!filter.should_capture(&request("GET", "/api/internal/users", &headers)));
+        assert!(!filter.should_capture(&request("GET", "/health", &headers)));
+        assert!(filter.should_capture(&request("GET", "/api/public/users", &headers)));
+        assert!(filter.should_capture(&request("GET", "/healthcheck", &headers)));
+    }
+
+    #[test]
+    fn test_rule_conditions() {
+        let filter = RouteFilter::new(
+            &rules(json!([{
+                "method": ["post", "PUT"],
+                "host": "*.example.com",
+                "path": "/api/**/internal",
+                "query": ["debug"],
+                "headers": { "X-Internal": "*" }
+            }])),
+            &[],
+            RoutePrecedence::Blacklist,
+        )
+        .unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("x-internal".to_string(), "1".to_string());
+        let no_headers = HashMap::new();
+
+        assert!(!filter.should_capture(&request("POST", "/api/internal?debug", &headers)));
+        assert!(!filter.should_capture(&request("PUT", "/api/v1/a/internal?debug=1", &headers)));
+        assert!(filter.should_capture(&request("GET", "/api/internal?debug", &headers)));
+        assert!(filter.should_capture(&request("POST", "/api/internal", &headers)));
+        assert!(filter.should_capture(&request("POST", "/api/internal?debug", &no_headers)));
+        assert!(filter.should_capture(&request("POST", "/api/v1/internal/x?debug", &headers)));
+    }
+
+    #[test]
+    fn test_whitelist_precedence() {
+        let blacklist = rules(json!([{ "path": "/api/admin/**" }]));
+        let whitelist = rules(json!([{ "path": "/api/**" }]));
+        let headers = HashMap::new();
+
+        let blacklist_wins =
+            RouteFilter::new(&blacklist, &whitelist, RoutePrecedence::Blacklist).unwrap();
+        assert!(blacklist_wins.should_capture(&request("GET", "/api/users", &headers)));
+        assert!(!blacklist_wins.should_capture(&request("GET", "/api/admin/users", &headers)));
+        assert!(!blacklist_wins.should_capture(&request("GET", "/other", &headers)));
+
+        let whitelist_wins =
+            RouteFilter::new(&blacklist, &whitelist, RoutePrecedence::Whitelist).unwrap();
+        assert!(whitelist_wins.should_capture(&request("GET", "/api/admin/users", &headers)));
+        assert!(!whitelist_wins.should_capture(&request("GET", "/other", &headers)));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let result = RouteFilter::new(
+            &rules(json!(["[invalid regex"])),
+            &[],
+            RoutePrecedence::Blacklist,
+        );
+
+        assert!(matches!(result, Err(TreblleError::Config(_))));
+    }
+
+    #[test]
+    fn test_capture_none() {
+        let headers = HashMap::new();
+
+        assert!(!RouteFilter::capture_none().should_capture(&request("GET", "/", &headers)));
+        assert!(matches!(
+            RouteFilter::from_config(&Config {
+                invalid_route_rules: true,
+                ..Config::fallback()
+            }),
+            Err(TreblleError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_rule_config() {
+        for value in [
+            json!({ "paht": "/health" }),
+            json!({}),
+            json!(""),
+            json!({ "path": 42 }),
+            json!({ "methods": ["GET", 1] }),
+            json!({ "headers": { "X-Internal": true } }),
+            json!(7),
+        ] {
+            assert!(
+                matches!(
+                    RouteRuleConfig::from_value(&value),
+                    Err(TreblleError::Config(_))
+                ),
+                "{}",
+                value
+            );
+        }
+
+        assert_eq!(
+            RouteRuleConfig::from_value(&json!({ "method": "get", "query": "debug" })).unwrap(),
+            RouteRuleConfig {
+                methods: vec!["get".to_string()],
+                query: vec!["debug".to_string()],
+                ..Default::default()
+            }
+        );
+    }
+}
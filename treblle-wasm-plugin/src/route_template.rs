@@ -0,0 +1,256 @@
+//! Route template module for the Treblle middleware.
+//!
+//! This module groups concrete request paths such as `/orders/8812/items/3` under a route
+//! such as `/orders/{id}/items/{itemId}`, either from configured templates or by
+//! replacing IDs, UUIDs, hashes and emails in path segments with placeholders, so
+//! Treblle sees one endpoint instead of thousands.
+
+use crate::config::Config;
+use crate::error::{Result, TreblleError};
+
+/// Shortest all-hex segment treated as a hash, e.g. a MongoDB ObjectId or an MD5 digest.
+const MIN_HASH_LENGTH: usize = 16;
+
+/// A segment of a route template.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder,
+}
+
+/// A configured route template, e.g. `/orders/{id}/items/{itemId}`.
+#[derive(Clone, Debug, PartialEq)]
+struct RouteTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl RouteTemplate {
+    fn parse(template: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            TreblleError::Config(format!("Invalid route template {}: {}", template, reason))
+        };
+
+        let path = template
+            .strip_prefix('/')
+            .ok_or_else(|| invalid("it must start with /"))?;
+
+        let segments = split_segments(path)
+            .map(
+                |segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) if !name.is_empty() && !name.contains(['{', '}']) => {
+                        Ok(Segment::Placeholder)
+                    }
+                    Some(_) => Err(invalid("placeholders need a name")),
+                    None if segment.contains(['{', '}']) => {
+                        Err(invalid("placeholders must span a whole segment"))
+                    }
+                    None => Ok(Segment::Literal(segment.to_string())),
+                },
+            )
+            .collect::<Result<_>>()?;
+
+        Ok(RouteTemplate {
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    fn matches(&self, segments: &[&str]) -> bool {
+        self.segments.len() == segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(segments)
+                .all(|(template, segment)| match template {
+                    Segment::Literal(literal) => literal == segment,
+                    Segment::Placeholder => !segment.is_empty(),
+                })
+    }
+}
+
+/// Derives the route a request path is grouped under.
+#[derive(Clone, Debug, Default)]
+pub struct RouteNormalizer {
+    templates: Vec<RouteTemplate>,
+    normalize: bool,
+}
+
+impl RouteNormalizer {
+    /// Creates a new `RouteNormalizer` from the middleware configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<RouteNormalizer>`, or `TreblleError::Config` if a route template
+    /// is invalid.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(RouteNormalizer {
+            templates: config
+                .route_templates
+                .iter()
+                .map(|template| RouteTemplate::parse(template))
+                .collect::<Result<_>>()?,
+            normalize: config.normalize_routes,
+        })
+    }
+
+    /// Derives the route of a request.
+    ///
+    /// The first matching template wins; otherwise, when normalization is enabled, IDs in
+    /// the path are replaced with `{id}`, `{uuid}`, `{hash}` or `{email}`.
+    ///
+    /// # Arguments
+    ///
+    /// * `uri` - The request URI; only its path is used.
+    ///
+    /// # Returns
+    ///
+    /// Returns the route, or `None` if it is the same as the path.
+    pub fn route(&self, uri: &str) -> Option<String> {
+        let path = uri.split(['?', '#']).next().unwrap_or_default();
+        let segments: Vec<&str> = split_segments(path.strip_prefix('/').unwrap_or(path)).collect();
+
+        if let Some(template) = self.templates.iter().find(|t| t.matches(&segments)) {
+            return Some(template.template.clone()).filter(|route| route != path);
+        }
+
+        if !self.normalize {
+            return None;
+        }
+
+        let mut changed = false;
+        let normalized: Vec<&str> = segments
+            .iter()
+            .map(|&segment| match placeholder(segment) {
+                Some(placeholder) => {
+                    changed = true;
+                    placeholder
+                }
+                None => segment,
+            })
+            .collect();
+
+        changed.then(|| format!("/{}", normalized.join("/")))
+    }
+}
+
+/// Splits a path without its leading `/` into segments, ignoring a trailing `/`.
+fn split_segments(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_suffix('/').unwrap_or(path);
+    path.split('/').filter(move |_| !path.is_empty())
+}
+
+/// Picks the placeholder replacing a path segment that looks like an identifier.
+fn placeholder(segment: &str) -> Option<&'static str> {
+    if segment.is_empty() {
+        None
+    } else if segment.bytes().all(|b| b.is_ascii_digit()) {
+        Some("{id}")
+    } else if is_uuid(segment) {
+        Some("{uuid}")
+    } else if segment.len() >= MIN_HASH_LENGTH && segment.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Some("{hash}")
+    } else if is_email(segment) {
+        Some("{email}")
+    } else {
+        None
+    }
+}
+
+/// Checks a segment is a UUID in its canonical 8-4-4-4-12 form.
+fn is_uuid(segment: &str) -> bool {
+    let groups: Vec<&str> = segment.split('-').collect();
+
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Checks a segment looks like an email address, with `@` possibly percent-encoded.
+fn is_email(segment: &str) -> bool {
+    let segment = segment.replace("%40", "@");
+
+    match segment.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain
+                    .split_once('.')
+                    .is_some_and(|(name, tld)| !name.is_empty() && !tld.is_empty())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(templates: &[&str], normalize: bool) -> RouteNormalizer {
+        RouteNormalizer::from_config(&Config {
+            route_templates: templates.iter().map(|t| t.to_string()).collect(),
+            normalize_routes: normalize,
+            ..Config::fallback()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route_from_template() {
+        let normalizer = normalizer(&["/orders/{id}/items/{itemId}", "/orders/{id}"], false);
+
+        assert_eq!(
+            normalizer.route("/orders/8812/items/3?expand=true"),
+            Some("/orders/{id}/items/{itemId}".to_string())
+        );
+        assert_eq!(
+            normalizer.route("/orders/abc/"),
+            Some("/orders/{id}".to_string())
+        );
+        assert_eq!(normalizer.route("/orders/8812/refunds/3"), None);
+        assert_eq!(normalizer.route("/orders"), None);
+    }
+
+    #[test]
+    fn test_route_normalization() {
+        let normalizer = normalizer(&[], true);
+
+        assert_eq!(
+            normalizer.route("/orders/8812/items/3"),
+            Some("/orders/{id}/items/{id}".to_string())
+        );
+        assert_eq!(
+            normalizer.route("/users/0b6c9f0e-3d1a-4f8e-9c2b-7a5d4e3f2a1b/avatar"),
+            Some("/users/{uuid}/avatar".to_string())
+        );
+        assert_eq!(
+            normalizer.route("/files/d41d8cd98f00b204e9800998ecf8427e"),
+            Some("/files/{hash}".to_string())
+        );
+        assert_eq!(
+            normalizer.route("/customers/jane%40example.com/orders"),
+            Some("/customers/{email}/orders".to_string())
+        );
+        assert_eq!(normalizer.route("/api/v2/health"), None);
+        assert_eq!(normalizer.route("/"), None);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in ["orders/{id}", "/orders/{}", "/orders/id-{id}"] {
+            assert!(
+                matches!(
+                    RouteNormalizer::from_config(&Config {
+                        route_templates: vec![template.to_string()],
+                        ..Config::fallback()
+                    }),
+                    Err(TreblleError::Config(_))
+                ),
+                "{}",
+                template
+            );
+        }
+    }
+}
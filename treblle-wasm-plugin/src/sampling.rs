@@ -0,0 +1,262 @@
+//! Sampling module for the Treblle middleware.
+//!
+//! This module decides which requests are shipped to Treblle, using a global
+//! sample rate, per-route sample rates and a max-events-per-second token bucket.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::error::{Result, TreblleError};
+use crate::route_filter::{RouteRequest, RouteRule, RouteRuleConfig};
+
+/// State of the xorshift generator used for sampling decisions.
+static RNG_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// A sample rate that applies to the routes matching a rule.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RouteSampleRate {
+    pub rule: RouteRuleConfig,
+    pub rate: f64,
+}
+
+impl RouteSampleRate {
+    /// Reads a per-route sample rate, e.g. `{ "path": "/api/**", "rate": 0.1 }`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the sample rate, or `TreblleError::Config` if the rate is missing or the
+    /// rule is invalid.
+    pub fn from_value(value: &Value) -> Result<Self> {
+        Ok(RouteSampleRate {
+            rate: value.get("rate").and_then(|v| v.as_f64()).ok_or_else(|| {
+                TreblleError::Config(format!("Route sample rate {} needs a numeric rate", value))
+            })?,
+            rule: RouteRuleConfig::from_value_with_keys(value, &["rate"])?,
+        })
+    }
+}
+
+/// Limits the number of captured events per second.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(events_per_second: u32, now: Instant) -> Self {
+        TokenBucket {
+            capacity: f64::from(events_per_second),
+            tokens: f64::from(events_per_second),
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.capacity).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Decides whether a request is sampled.
+#[derive(Debug)]
+pub struct Sampler {
+    sample_rate: f64,
+    route_rates: Vec<(RouteRule, f64)>,
+    bucket: Option<Mutex<TokenBucket>>,
+    always_capture_errors: bool,
+}
+
+impl Sampler {
+    /// Creates a new `Sampler` from the middleware configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<Sampler>`, or `TreblleError::Config` if a rate is outside `0.0..=1.0`
+    /// or a route rule is invalid.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let route_rates = config
+            .route_sample_rates
+            .iter()
+            .map(|route| Ok((RouteRule::compile(&route.rule)?, validate_rate(route.rate)?)))
+            .collect::<Result<_>>()?;
+
+        Ok(Sampler {
+            sample_rate: validate_rate(config.sample_rate)?,
+            route_rates,
+            bucket: (config.max_events_per_second > 0).then(|| {
+                Mutex::new(TokenBucket::new(
+                    config.max_events_per_second,
+                    Instant::now(),
+                ))
+            }),
+            always_capture_errors: config.always_capture_errors,
+        })
+    }
+
+    /// Checks if a request should be captured.
+    ///
+    /// The first matching per-route rate takes precedence over the global rate. Requests
+    /// that pass the rate still need a token when `maxEventsPerSecond` is set.
+    pub fn should_sample(&self, request: &RouteRequest) -> bool {
+        let rate = self
+            .route_rates
+            .iter()
+            .find(|(rule, _)| rule.matches(request))
+            .map_or(self.sample_rate, |(_, rate)| *rate);
+
+        if rate < 1.0 && next_random() >= rate {
+            return false;
+        }
+
+        match &self.bucket {
+            Some(bucket) => bucket
+                .lock()
+                .map(|mut bucket| bucket.try_take(Instant::now()))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Returns whether unsampled requests are still captured when they fail (status >= 400).
+    pub fn always_capture_errors(&self) -> bool {
+        self.always_capture_errors
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler {
+            sample_rate: 1.0,
+            route_rates: Vec::new(),
+            bucket: None,
+            always_capture_errors: false,
+        }
+    }
+}
+
+fn validate_rate(rate: f64) -> Result<f64> {
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(TreblleError::Config(format!(
+            "Sample rate must be between 0.0 and 1.0, got {}",
+            rate
+        )))
+    }
+}
+
+/// Returns a pseudo-random number in `0.0..1.0`.
+///
+/// Sampling only needs an even spread, so a xorshift generator seeded from the clock
+/// is enough and avoids a round-trip to the host for every request.
+fn next_random() -> f64 {
+    let mut x = RNG_STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d)
+            | 1;
+    }
+
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RNG_STATE.store(x, Ordering::Relaxed);
+
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn request<'a>(uri: &'a str, headers: &'a HashMap<String, String>) -> RouteRequest<'a> {
+        RouteRequest {
+            method: "GET",
+            host: "api.example.com",
+            uri,
+            headers,
+        }
+    }
+
+    #[test]
+    fn test_global_sample_rate() {
+        let headers = HashMap::new();
+        let never = Sampler::from_config(&Config {
+            sample_rate: 0.0,
+            ..Config::fallback()
+        })
+        .unwrap();
+        let always = Sampler::default();
+
+        assert!((0..100).all(|_| !never.should_sample(&request("/users", &headers))));
+        assert!((0..100).all(|_| always.should_sample(&request("/users", &headers))));
+    }
+
+    #[test]
+    fn test_route_sample_rate_overrides_global() {
+        let headers = HashMap::new();
+        let sampler = Sampler::from_config(&Config {
+            sample_rate: 0.0,
+            route_sample_rates: vec![RouteSampleRate::from_value(
+                &json!({ "path": "/orders/**", "rate": 1.0 }),
+            )
+            .unwrap()],
+            ..Config::fallback()
+        })
+        .unwrap();
+
+        assert!(sampler.should_sample(&request("/orders/42", &headers)));
+        assert!(!sampler.should_sample(&request("/users/42", &headers)));
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_invalid_sample_rate() {
+        let result = Sampler::from_config(&Config {
+            sample_rate: 1.5,
+            ..Config::fallback()
+        });
+
+        assert!(matches!(result, Err(TreblleError::Config(_))));
+    }
+
+    #[test]
+    fn test_next_random_range() {
+        assert!((0..1000)
+            .map(|_| next_random())
+            .all(|r| (0.0..1.0).contains(&r)));
+    }
+}
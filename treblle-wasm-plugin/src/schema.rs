@@ -21,6 +21,56 @@ pub struct PayloadData {
     pub request: RequestInfo,
     pub response: ResponseInfo,
     pub errors: Vec<ErrorInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masked_fields: Vec<MaskedField>,
+    #[serde(default)]
+    pub timing: TimingInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceInfo>,
+    /// Tags from `staticTags`, `tagsFromHeaders` and `tagsFromRoute`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserInfo>,
+}
+
+/// Identifies the caller of a request.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct UserInfo {
+    pub id: String,
+    /// Where the ID was read: `header` or `jwt`.
+    pub source: String,
+}
+
+/// Represents the distributed trace a request belongs to.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct TraceInfo {
+    pub trace_id: String,
+    /// The span of the caller, which is the parent of the upstream request.
+    pub span_id: String,
+    pub sampled: bool,
+    /// Vendor-specific trace data from the `tracestate` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+    /// Where the context came from: `traceparent`, `b3` or `generated`.
+    pub source: String,
+}
+
+/// Represents where the time of a request was spent, in milliseconds.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct TimingInfo {
+    /// From the request reaching the middleware to the response reaching it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<f64>,
+    /// From the request leaving the middleware to the response reaching it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_ms: Option<f64>,
+    /// Time spent in the middleware itself, excluding the upstream call.
+    pub plugin_overhead_ms: f64,
+    /// Time spent decoding, parsing and masking headers and bodies.
+    pub masking_ms: f64,
+    /// Time spent shaping bodies to the payload limits before encoding.
+    pub shaping_ms: f64,
 }
 
 /// Represents server information.
@@ -36,6 +86,13 @@ pub struct ServerInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encoding: Option<String>,
     pub os: OsInfo,
+    /// Name of the node running the middleware, to tell edge nodes apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
 }
 
 /// Represents operating system information.
@@ -65,9 +122,43 @@ pub struct RequestInfo {
     pub url: String,
     pub user_agent: String,
     pub method: String,
+    /// The correlation ID from the `requestIdHeader` header, generated if missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Size of the body on the wire, before `Content-Encoding` is undone.
+    #[serde(default, serialize_with = "serialize_size")]
+    pub size: usize,
+    /// Size of the body once decompressed, when it was compressed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_size: Option<usize>,
     pub headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<serde_json::Value>,
+    /// Why the body could not be decoded, when it is reported as `null`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_error: Option<String>,
+    /// The route this request is grouped under, when it differs from the URL path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graphql: Option<GraphQlInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub jsonrpc: Vec<JsonRpcCall>,
+}
+
+/// Represents the GraphQL operation executed by a request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GraphQlInfo {
+    pub operation_type: String,
+    pub operation_name: Option<String>,
+}
+
+/// Represents a JSON-RPC call made by a request; `id` is `null` for notifications.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JsonRpcCall {
+    pub method: String,
+    #[serde(default)]
+    pub id: serde_json::Value,
 }
 
 /// Represents HTTP response information.
@@ -75,12 +166,24 @@ pub struct RequestInfo {
 pub struct ResponseInfo {
     #[serde(serialize_with = "serialize_code")]
     pub code: u32,
+    /// Size of the body on the wire, before `Content-Encoding` is undone.
     #[serde(serialize_with = "serialize_size")]
     pub size: usize,
+    /// Size of the body once decompressed, when it was compressed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_size: Option<usize>,
     pub load_time: f64,
     pub headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<serde_json::Value>,
+    /// Why the body could not be decoded, when it is reported as `null`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_error: Option<String>,
+    /// The response is a stream whose body was not captured; `load_time` then covers
+    /// the whole stream. The time to first byte cannot be reported, since the middleware
+    /// only sees the response once the stream has ended.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub streaming: bool,
 }
 
 /// Represents error information.
@@ -94,6 +197,13 @@ pub struct ErrorInfo {
     pub line: u32,
 }
 
+/// Represents a value redacted by the masking engine, without the value itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaskedField {
+    pub path: String,
+    pub rule: String,
+}
+
 fn serialize_code<S>(code: &u32, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -0,0 +1,181 @@
+//! Streaming module for the Treblle middleware.
+//!
+//! This module recognizes streaming responses, such as Server-Sent Events and NDJSON,
+//! whose bodies must not be buffered, and the routes where response buffering is
+//! always disabled.
+//!
+//! Buffering is decided when the request arrives, before the response headers are known,
+//! so streams are recognized from the request's `Accept` header too. A stream that is
+//! only recognized from its response headers has already been buffered by Traefik; its
+//! body is still not captured, but use `unbufferedRoutes` to keep it streaming.
+
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::constants::HEADER_VALUE_SEPARATOR;
+use crate::error::Result;
+use crate::route_filter::{RouteRequest, RouteRule};
+use crate::utils::get_header_value;
+
+/// Content types of long-lived streams, matched against the media type.
+const STREAMING_CONTENT_TYPES: [&str; 6] = [
+    "text/event-stream",
+    "application/x-ndjson",
+    "application/ndjson",
+    "application/jsonl",
+    "application/x-jsonlines",
+    "application/stream+json",
+];
+
+/// Checks if a request asks for a stream, based on its `Accept` header.
+///
+/// # Arguments
+///
+/// * `headers` - The request headers.
+///
+/// # Returns
+///
+/// Returns `true` if the client accepts one of the streaming content types, so the
+/// response must not be buffered.
+pub fn is_streaming_request(headers: &HashMap<String, String>) -> bool {
+    get_header_value(headers, "Accept").is_some_and(|accept| {
+        accept
+            .split([',', HEADER_VALUE_SEPARATOR])
+            .any(is_streaming_content_type)
+    })
+}
+
+/// Checks if a response is a stream, based on its headers.
+///
+/// A response is a stream if its content type is one of the streaming types, or if the
+/// upstream asked proxies not to buffer it with `X-Accel-Buffering: no`.
+///
+/// # Arguments
+///
+/// * `headers` - The response headers.
+///
+/// # Returns
+///
+/// Returns `true` if only the response metadata should be captured.
+pub fn is_streaming_response(headers: &HashMap<String, String>) -> bool {
+    let content_type = get_header_value(headers, "Content-Type").unwrap_or_default();
+
+    is_streaming_content_type(&content_type)
+        || get_header_value(headers, "X-Accel-Buffering")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("no"))
+}
+
+/// Checks if the media type of a content type is one of the streaming types.
+fn is_streaming_content_type(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    STREAMING_CONTENT_TYPES.contains(&media_type.as_str())
+}
+
+/// Routes whose responses are never buffered.
+#[derive(Clone, Debug, Default)]
+pub struct UnbufferedRoutes {
+    rules: Vec<RouteRule>,
+}
+
+impl UnbufferedRoutes {
+    /// Creates the unbuffered routes from the middleware configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<UnbufferedRoutes>`, or `TreblleError::Config` if a rule is invalid.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(UnbufferedRoutes {
+            rules: config
+                .unbuffered_routes
+                .iter()
+                .map(RouteRule::compile)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Checks if the response of a request must not be buffered.
+    pub fn matches(&self, request: &RouteRequest) -> bool {
+        self.rules.iter().any(|rule| rule.matches(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::route_filter::RouteRuleConfig;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_is_streaming_response() {
+        assert!(is_streaming_response(&headers(&[(
+            "Content-Type",
+            "text/event-stream; charset=utf-8"
+        )])));
+        assert!(is_streaming_response(&headers(&[(
+            "content-type",
+            "application/x-ndjson"
+        )])));
+        assert!(is_streaming_response(&headers(&[
+            ("Content-Type", "application/json"),
+            ("X-Accel-Buffering", "no")
+        ])));
+        assert!(!is_streaming_response(&headers(&[(
+            "Content-Type",
+            "application/json"
+        )])));
+        assert!(!is_streaming_response(&HashMap::new()));
+    }
+
+    #[test]
+    fn test_is_streaming_request() {
+        assert!(is_streaming_request(&headers(&[(
+            "Accept",
+            "text/event-stream"
+        )])));
+        assert!(is_streaming_request(&headers(&[(
+            "accept",
+            "application/json;q=0.9, application/x-ndjson"
+        )])));
+        assert!(is_streaming_request(&headers(&[(
+            "Accept",
+            "application/json\0text/event-stream"
+        )])));
+        assert!(!is_streaming_request(&headers(&[("Accept", "*/*")])));
+        assert!(!is_streaming_request(&HashMap::new()));
+    }
+
+    #[test]
+    fn test_unbuffered_routes() {
+        let config = Config {
+            unbuffered_routes: vec![RouteRuleConfig {
+                path: Some("/events/**".to_string()),
+                ..Default::default()
+            }],
+            ..Config::fallback()
+        };
+        let routes = UnbufferedRoutes::from_config(&config).unwrap();
+        let headers = HashMap::new();
+        let request = |uri| RouteRequest {
+            method: "GET",
+            host: "api.example.com",
+            uri,
+            headers: &headers,
+        };
+
+        assert!(routes.matches(&request("/events/orders?since=1")));
+        assert!(!routes.matches(&request("/orders")));
+        assert!(!UnbufferedRoutes::default().matches(&request("/events/orders")));
+    }
+}
@@ -0,0 +1,195 @@
+//! Tags module for the Treblle middleware.
+//!
+//! This module attaches deployment-specific tags to captured events, from static
+//! configuration, request headers and named captures of route patterns, so Treblle data
+//! can be segmented by tenant, environment or service.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::error::{Result, TreblleError};
+use crate::masking::check_reportable_header;
+use crate::utils::get_header_value;
+
+/// A header whose value is copied into a tag.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct HeaderTag {
+    pub header: String,
+    pub tag: String,
+}
+
+impl HeaderTag {
+    /// Reads the `tagsFromHeaders` setting, a map of header names to tag names.
+    pub fn from_value(value: &Value) -> Vec<Self> {
+        value
+            .as_object()
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(header, tag)| {
+                        Some(HeaderTag {
+                            header: header.clone(),
+                            tag: tag.as_str()?.to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Extracts the tags of a request.
+#[derive(Clone, Debug, Default)]
+pub struct TagExtractor {
+    static_tags: HashMap<String, String>,
+    header_tags: Vec<HeaderTag>,
+    route_patterns: Vec<Regex>,
+}
+
+impl TagExtractor {
+    /// Creates a new `TagExtractor` from the middleware configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<TagExtractor>`, or `TreblleError::Config` if a route pattern is
+    /// invalid or has no named capture group, or if a header that masking hides would be
+    /// copied.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        for header_tag in &config.tags_from_headers {
+            check_reportable_header(config, &header_tag.header, "a tag")?;
+        }
+
+        let route_patterns = config
+            .tags_from_route
+            .iter()
+            .map(|pattern| {
+                let re = Regex::new(pattern).map_err(|e| {
+                    TreblleError::Config(format!("Invalid route tag pattern {}: {}", pattern, e))
+                })?;
+
+                if re.capture_names().flatten().next().is_none() {
+                    return Err(TreblleError::Config(format!(
+                        "Route tag pattern {} has no named capture group",
+                        pattern
+                    )));
+                }
+
+                Ok(re)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(TagExtractor {
+            static_tags: config.static_tags.clone(),
+            header_tags: config.tags_from_headers.clone(),
+            route_patterns,
+        })
+    }
+
+    /// Extracts the tags of a request.
+    ///
+    /// Header tags override static tags, and route captures override both.
+    ///
+    /// # Arguments
+    ///
+    /// * `uri` - The request URI; route patterns are matched against its path.
+    /// * `headers` - The request headers.
+    ///
+    /// # Returns
+    ///
+    /// Returns the tags, without empty values.
+    pub fn extract(&self, uri: &str, headers: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut tags = self.static_tags.clone();
+
+        for header_tag in &self.header_tags {
+            if let Some(value) = get_header_value(headers, &header_tag.header) {
+                tags.insert(header_tag.tag.clone(), value.trim().to_string());
+            }
+        }
+
+        let path = uri.split(['?', '#']).next().unwrap_or_default();
+        for re in &self.route_patterns {
+            if let Some(caps) = re.captures(path) {
+                for name in re.capture_names().flatten() {
+                    if let Some(value) = caps.name(name) {
+                        tags.insert(name.to_string(), value.as_str().to_string());
+                    }
+                }
+            }
+        }
+
+        tags.retain(|_, value| !value.is_empty());
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn extractor() -> TagExtractor {
+        TagExtractor::from_config(&Config {
+            static_tags: HashMap::from([
+                ("environment".to_string(), "production".to_string()),
+                ("tenant".to_string(), "default".to_string()),
+            ]),
+            tags_from_headers: HeaderTag::from_value(&json!({ "X-Tenant-Id": "tenant" })),
+            tags_from_route: vec![r"^/services/(?P<service>[^/]+)/".to_string()],
+            ..Config::fallback()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_extract() {
+        let headers = HashMap::from([("x-tenant-id".to_string(), "acme".to_string())]);
+
+        let tags = extractor().extract("/services/billing/invoices?page=2", &headers);
+
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags["environment"], "production");
+        assert_eq!(tags["tenant"], "acme");
+        assert_eq!(tags["service"], "billing");
+
+        let tags = extractor().extract("/health", &HashMap::new());
+
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags["tenant"], "default");
+    }
+
+    #[test]
+    fn test_from_config_errors() {
+        for config in [
+            Config {
+                tags_from_route: vec!["^/services/([^/]+)".to_string()],
+                ..Config::fallback()
+            },
+            Config {
+                tags_from_route: vec!["(?P<broken".to_string()],
+                ..Config::fallback()
+            },
+            Config {
+                tags_from_headers: HeaderTag::from_value(&json!({ "Authorization": "token" })),
+                ..Config::fallback()
+            },
+            Config {
+                tags_from_headers: HeaderTag::from_value(&json!({ "X-Api-Secret": "secret" })),
+                sensitive_keys_regex: "(?i)secret".to_string(),
+                ..Config::fallback()
+            },
+            Config {
+                tags_from_headers: HeaderTag::from_value(&json!({ "X-Tenant-Id": "tenant" })),
+                header_allowlist: vec!["Content-Type".to_string()],
+                ..Config::fallback()
+            },
+        ] {
+            assert!(matches!(
+                TagExtractor::from_config(&config),
+                Err(TreblleError::Config(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Trace context module for the Treblle middleware.
+//!
+//! This module reads the W3C Trace Context (`traceparent`/`tracestate`) and, optionally,
+//! B3 headers of a request, and generates a new context when the request has none, so
+//! captured events can be linked to the distributed trace they belong to.
+
+use std::collections::HashMap;
+
+use crate::error::Result;
+use crate::schema::TraceInfo;
+use crate::utils::{get_header_value, random_bytes, to_hex};
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACESTATE_HEADER: &str = "tracestate";
+
+/// The only `traceparent` version this module emits.
+const TRACEPARENT_VERSION: &str = "00";
+
+/// A trace context and where it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    /// 32 lowercase hex digits.
+    pub trace_id: String,
+    /// 16 lowercase hex digits identifying the caller's span.
+    pub span_id: String,
+    pub sampled: bool,
+    pub tracestate: Option<String>,
+    /// How the context was obtained: `traceparent`, `b3` or `generated`.
+    pub source: &'static str,
+}
+
+impl TraceContext {
+    /// Reads the trace context of a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The request headers.
+    /// * `accept_b3` - Whether B3 headers are read when `traceparent` is absent.
+    ///
+    /// # Returns
+    ///
+    /// Returns the trace context, or `None` if the request carries no valid one.
+    pub fn from_headers(headers: &HashMap<String, String>, accept_b3: bool) -> Option<Self> {
+        if let Some(context) = get_header_value(headers, TRACEPARENT_HEADER)
+            .and_then(|value| parse_traceparent(&value))
+        {
+            return Some(TraceContext {
+                tracestate: get_header_value(headers, TRACESTATE_HEADER)
+                    .filter(|value| !value.trim().is_empty()),
+                ..context
+            });
+        }
+
+        if accept_b3 {
+            return parse_b3(headers);
+        }
+
+        None
+    }
+
+    /// Generates a new sampled trace context with random IDs.
+    ///
+    /// # Returns
+    ///
+    /// Returns the new context, or `TreblleError::HostFunction` if the host provides no
+    /// randomness.
+    pub fn generate() -> Result<Self> {
+        Ok(TraceContext {
+            trace_id: random_id(16)?,
+            span_id: random_id(8)?,
+            sampled: true,
+            tracestate: None,
+            source: "generated",
+        })
+    }
+
+    /// Formats the context as a `traceparent` header value.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            TRACEPARENT_VERSION,
+            self.trace_id,
+            self.span_id,
+            if self.sampled { "01" } else { "00" }
+        )
+    }
+
+    /// Converts the context into the trace information reported to Treblle.
+    pub fn to_trace_info(&self) -> TraceInfo {
+        TraceInfo {
+            trace_id: self.trace_id.clone(),
+            span_id: self.span_id.clone(),
+            sampled: self.sampled,
+            tracestate: self.tracestate.clone(),
+            source: self.source.to_string(),
+        }
+    }
+}
+
+/// Parses a `traceparent` header, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+///
+/// Versions other than `00` are accepted as long as the first four fields are valid, as
+/// the specification requires; version `ff` is invalid.
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let flags = parts.next()?;
+
+    if !is_hex(version, 2)
+        || version.eq_ignore_ascii_case("ff")
+        || (version == TRACEPARENT_VERSION && parts.next().is_some())
+        || !is_id(trace_id, 32)
+        || !is_id(span_id, 16)
+        || !is_hex(flags, 2)
+    {
+        return None;
+    }
+
+    Some(TraceContext {
+        trace_id: trace_id.to_lowercase(),
+        span_id: span_id.to_lowercase(),
+        sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+        tracestate: None,
+        source: "traceparent",
+    })
+}
+
+/// Parses the single `b3` header or the multi-header `X-B3-*` form.
+///
+/// 64-bit B3 trace IDs are left-padded with zeros to the 128-bit W3C format.
+fn parse_b3(headers: &HashMap<String, String>) -> Option<TraceContext> {
+    let (trace_id, span_id, sampled) = match get_header_value(headers, "b3") {
+        Some(single) => {
+            let mut parts = single.trim().split('-');
+            let trace_id = parts.next()?.to_string();
+            let span_id = parts.next()?.to_string();
+            (trace_id, span_id, parts.next().map(String::from))
+        }
+        None => (
+            get_header_value(headers, "X-B3-TraceId")?,
+            get_header_value(headers, "X-B3-SpanId")?,
+            get_header_value(headers, "X-B3-Sampled")
+                .or_else(|| get_header_value(headers, "X-B3-Flags")),
+        ),
+    };
+
+    let trace_id = trace_id.trim();
+    let trace_id = match trace_id.len() {
+        16 => format!("{:0>32}", trace_id),
+        _ => trace_id.to_string(),
+    };
+    let span_id = span_id.trim();
+
+    if !is_id(&trace_id, 32) || !is_id(span_id, 16) {
+        return None;
+    }
+
+    Some(TraceContext {
+        trace_id: trace_id.to_lowercase(),
+        span_id: span_id.to_lowercase(),
+        // Debug (`d`) implies sampled; an absent decision defers to us, and we sample.
+        sampled: !matches!(sampled.as_deref().map(str::trim), Some("0") | Some("false")),
+        tracestate: None,
+        source: "b3",
+    })
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Checks an ID is hex of the given length and not all zeros, which marks it invalid.
+fn is_id(value: &str, len: usize) -> bool {
+    is_hex(value, len) && value.bytes().any(|b| b != b'0')
+}
+
+/// Generates a random, non-zero ID of `bytes` bytes as lowercase hex.
+fn random_id(bytes: usize) -> Result<String> {
+    let mut id = random_bytes(bytes)?;
+
+    if id.iter().all(|&b| b == 0) {
+        id[bytes - 1] = 1;
+    }
+
+    Ok(to_hex(&id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_traceparent() {
+        let context = TraceContext::from_headers(
+            &headers(&[
+                (
+                    "Traceparent",
+                    "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+                ),
+                ("Tracestate", "congo=t61rcWkgMzE"),
+            ]),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id, "00f067aa0ba902b7");
+        assert!(context.sampled);
+        assert_eq!(context.tracestate, Some("congo=t61rcWkgMzE".to_string()));
+        assert_eq!(
+            context.traceparent(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        for invalid in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "garbage",
+        ] {
+            assert_eq!(
+                TraceContext::from_headers(&headers(&[("traceparent", invalid)]), false),
+                None,
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_b3() {
+        let single = headers(&[("b3", "a3ce929d0e0e4736-00f067aa0ba902b7-0")]);
+        let multi = headers(&[
+            ("X-B3-TraceId", "4bf92f3577b34da6a3ce929d0e0e4736"),
+            ("X-B3-SpanId", "00f067aa0ba902b7"),
+        ]);
+
+        assert_eq!(TraceContext::from_headers(&single, false), None);
+
+        let context = TraceContext::from_headers(&single, true).unwrap();
+        assert_eq!(context.trace_id, "0000000000000000a3ce929d0e0e4736");
+        assert!(!context.sampled);
+        assert_eq!(context.source, "b3");
+
+        let context = TraceContext::from_headers(&multi, true).unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(context.sampled);
+    }
+
+    #[test]
+    fn test_generate() {
+        let first = TraceContext::generate().unwrap();
+        let second = TraceContext::generate().unwrap();
+
+        assert!(is_id(&first.trace_id, 32));
+        assert!(is_id(&first.span_id, 16));
+        assert_ne!(first.trace_id, second.trace_id);
+        assert_eq!(
+            parse_traceparent(&first.traceparent()).map(|c| c.trace_id),
+            Some(first.trace_id)
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! User ID module for the Treblle middleware.
+//!
+//! This module identifies the caller of a request, from a configured header or from a
+//! claim of the `Authorization: Bearer` JWT, so Treblle can associate calls with
+//! customers. The JWT is decoded but not verified; the token itself stays masked.
+
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::error::{Result, TreblleError};
+use crate::masking::check_reportable_header;
+use crate::schema::UserInfo;
+use crate::utils::get_header_value;
+
+/// Longest user ID that is reported, to keep oversized values out of the payload.
+const MAX_USER_ID_LENGTH: usize = 200;
+
+/// Resolves the user ID of a request.
+#[derive(Clone, Debug, Default)]
+pub struct UserIdResolver {
+    header: Option<String>,
+    /// Path of the JWT claim, split on `.` to reach nested claims.
+    claim: Option<Vec<String>>,
+}
+
+impl UserIdResolver {
+    /// Creates a new `UserIdResolver` from the middleware configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<UserIdResolver>`, or `TreblleError::Config` if masking hides the
+    /// user ID header or the claim path has an empty segment.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        if let Some(header) = &config.user_id_header {
+            check_reportable_header(config, header, "the user ID")?;
+        }
+
+        let claim = config
+            .user_id_claim
+            .as_ref()
+            .map(|claim| {
+                let path: Vec<String> = claim.split('.').map(String::from).collect();
+                if path.iter().any(|segment| segment.is_empty()) {
+                    return Err(TreblleError::Config(format!(
+                        "Invalid user ID claim {}",
+                        claim
+                    )));
+                }
+                Ok(path)
+            })
+            .transpose()?;
+
+        Ok(UserIdResolver {
+            header: config.user_id_header.clone(),
+            claim,
+        })
+    }
+
+    /// Resolves the user ID of a request.
+    ///
+    /// The configured header takes precedence over the JWT claim.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The request headers.
+    ///
+    /// # Returns
+    ///
+    /// Returns the user ID and where it came from, or `None` if the request has none.
+    pub fn resolve(&self, headers: &HashMap<String, String>) -> Option<UserInfo> {
+        let from_header = self
+            .header
+            .as_ref()
+            .and_then(|header| get_header_value(headers, header))
+            .and_then(|id| sanitize(&id))
+            .map(|id| UserInfo {
+                id,
+                source: "header".to_string(),
+            });
+
+        from_header.or_else(|| {
+            let path = self.claim.as_ref()?;
+            let claims = bearer_claims(&get_header_value(headers, "Authorization")?)?;
+
+            let claim = path
+                .iter()
+                .try_fold(&claims, |value, segment| value.get(segment))?;
+
+            let id = match claim {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                _ => return None,
+            };
+
+            Some(UserInfo {
+                id: sanitize(&id)?,
+                source: "jwt".to_string(),
+            })
+        })
+    }
+}
+
+/// Decodes the claims of a bearer JWT, without verifying its signature.
+fn bearer_claims(authorization: &str) -> Option<Value> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let mut parts = token.trim().split('.');
+    let (_header, claims, _signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let claims = URL_SAFE_NO_PAD.decode(claims.trim_end_matches('=')).ok()?;
+
+    serde_json::from_slice::<Value>(&claims)
+        .ok()
+        .filter(Value::is_object)
+}
+
+/// Trims a user ID, rejecting empty or oversized values.
+fn sanitize(id: &str) -> Option<String> {
+    let id = id.trim();
+    (!id.is_empty() && id.chars().count() <= MAX_USER_ID_LENGTH).then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn bearer(claims: Value) -> HashMap<String, String> {
+        let token = format!(
+            "eyJhbGciOiJIUzI1NiJ9.{}.c2lnbmF0dXJl",
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        HashMap::from([("authorization".to_string(), format!("Bearer {}", token))])
+    }
+
+    fn resolver(header: Option<&str>, claim: Option<&str>) -> UserIdResolver {
+        UserIdResolver::from_config(&Config {
+            user_id_header: header.map(String::from),
+            user_id_claim: claim.map(String::from),
+            ..Config::fallback()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_from_jwt() {
+        let headers = bearer(json!({ "sub": "user-42", "org": { "id": 7 } }));
+
+        assert_eq!(
+            resolver(None, Some("sub")).resolve(&headers),
+            Some(UserInfo {
+                id: "user-42".to_string(),
+                source: "jwt".to_string(),
+            })
+        );
+        assert_eq!(
+            resolver(None, Some("org.id"))
+                .resolve(&headers)
+                .map(|u| u.id),
+            Some("7".to_string())
+        );
+        assert_eq!(resolver(None, Some("email")).resolve(&headers), None);
+        assert_eq!(resolver(None, None).resolve(&headers), None);
+
+        let basic = HashMap::from([(
+            "authorization".to_string(),
+            "Basic dXNlcjpwYXNz".to_string(),
+        )]);
+        assert_eq!(resolver(None, Some("sub")).resolve(&basic), None);
+    }
+
+    #[test]
+    fn test_resolve_from_header() {
+        let mut headers = bearer(json!({ "sub": "user-42" }));
+        headers.insert("x-customer-id".to_string(), " acme ".to_string());
+
+        let user = resolver(Some("X-Customer-Id"), Some("sub")).resolve(&headers);
+        assert_eq!(
+            user.map(|u| (u.id, u.source)),
+            Some(("acme".to_string(), "header".to_string()))
+        );
+
+        headers.remove("x-customer-id");
+        let user = resolver(Some("X-Customer-Id"), Some("sub")).resolve(&headers);
+        assert_eq!(user.map(|u| u.source), Some("jwt".to_string()));
+    }
+
+    #[test]
+    fn test_from_config_errors() {
+        for config in [
+            Config {
+                user_id_header: Some("Authorization".to_string()),
+                ..Config::fallback()
+            },
+            Config {
+                user_id_header: Some("X-Session-Token".to_string()),
+                sensitive_keys_regex: "(?i)token".to_string(),
+                ..Config::fallback()
+            },
+            Config {
+                user_id_claim: Some("org..id".to_string()),
+                ..Config::fallback()
+            },
+        ] {
+            assert!(matches!(
+                UserIdResolver::from_config(&config),
+                Err(TreblleError::Config(_))
+            ));
+        }
+    }
+}
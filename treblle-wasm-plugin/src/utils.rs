@@ -1,13 +1,21 @@
 //! Utility functions for parsing and masking data.
 
 use chrono::Utc;
-use regex::Regex;
-use serde_json::{Map, Value};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt::Write;
 use std::time::Instant;
 
+use crate::body_parser::{try_parse_body, BodyKind};
 use crate::config::Config;
+use crate::decompression::decode_body;
 use crate::error::{Result, TreblleError};
-use crate::schema::{RequestInfo, ResponseInfo};
+use crate::graphql;
+use crate::jsonrpc;
+use crate::logger::{log, LogLevel};
+use crate::masking::Masker;
+use crate::schema::{MaskedField, RequestInfo, ResponseInfo};
+use crate::streaming;
 
 /// Parses and processes request information.
 ///
@@ -17,25 +25,51 @@ use crate::schema::{RequestInfo, ResponseInfo};
 /// * `uri` - The URI of the request.
 /// * `headers` - A map of request headers.
 /// * `body` - The raw body of the request.
-/// * `config` - The configuration containing sensitive data patterns.
+/// * `ip` - The client IP, resolved from the peer address and trusted proxy headers.
+/// * `config` - The configuration of body capture and the header allowlist.
+/// * `masker` - The masker built from the sensitive data patterns.
+/// * `masked_fields` - Collects the paths of every masked value for the masking audit.
 ///
 /// # Returns
 ///
 /// Returns a `Result<RequestInfo>` containing the processed request information,
 /// or an error if processing fails.
+#[allow(clippy::too_many_arguments)]
 pub fn parse_request(
     method: String,
     uri: String,
     headers: std::collections::HashMap<String, String>,
     body: &[u8],
+    ip: Option<String>,
     config: &Config,
+    masker: &Masker,
+    masked_fields: &mut Vec<MaskedField>,
 ) -> Result<RequestInfo> {
-    let ip = extract_ip_from_headers(&headers).unwrap_or_else(|| "Unknown".to_string());
+    let ip = ip.unwrap_or_else(|| "Unknown".to_string());
     let user_agent = headers.get("User-Agent").cloned().unwrap_or_default();
 
-    let parsed_body = parse_json_body(body);
-    let masked_body = mask_sensitive_data(&parsed_body, &config.sensitive_keys_regex)?;
-    let masked_headers = mask_sensitive_headers(&headers, &config.sensitive_keys_regex)?;
+    let content_type = get_header_value(&headers, "Content-Type").unwrap_or_default();
+
+    let CapturedBody {
+        value: mut parsed_body,
+        error: body_error,
+        decoded_size,
+    } = parse_captured_body(body, &headers, config);
+
+    let graphql = graphql::detect_operation(&parsed_body, &content_type);
+    if graphql.is_some() {
+        graphql::prepare_body(&mut parsed_body, masker, "request.body", masked_fields);
+    }
+    let route_path = graphql
+        .as_ref()
+        .filter(|_| config.graphql_operation_as_route)
+        .and_then(|operation| graphql::operation_route(&uri, operation));
+    let jsonrpc = jsonrpc::detect_calls(&parsed_body);
+
+    let masked_body = masker.mask_audited(&parsed_body, "request.body", masked_fields);
+    let allowed_headers = filter_allowed_headers(&headers, &config.header_allowlist);
+    let masked_headers =
+        masker.mask_headers_audited(&allowed_headers, "request.headers", masked_fields);
 
     Ok(RequestInfo {
         timestamp: Utc::now().to_rfc3339(),
@@ -43,8 +77,15 @@ pub fn parse_request(
         url: uri,
         user_agent,
         method,
+        request_id: None,
+        size: wire_size(body, &headers),
+        decoded_size,
         headers: masked_headers,
         body: serde_json::to_value(masked_body).ok(),
+        body_error,
+        route_path,
+        graphql,
+        jsonrpc,
     })
 }
 
@@ -56,7 +97,9 @@ pub fn parse_request(
 /// * `headers` - A map of response headers.
 /// * `body` - The raw body of the response.
 /// * `start_time` - The time when the request processing started.
-/// * `config` - The configuration containing sensitive data patterns.
+/// * `config` - The configuration of body capture and the header allowlist.
+/// * `masker` - The masker built from the sensitive data patterns.
+/// * `masked_fields` - Collects the paths of every masked value for the masking audit.
 ///
 /// # Returns
 ///
@@ -68,20 +111,165 @@ pub fn parse_response(
     body: &[u8],
     start_time: Instant,
     config: &Config,
+    masker: &Masker,
+    masked_fields: &mut Vec<MaskedField>,
 ) -> Result<ResponseInfo> {
-    let parsed_body = parse_json_body(body);
-    let masked_body = mask_sensitive_data(&parsed_body, &config.sensitive_keys_regex)?;
-    let masked_headers = mask_sensitive_headers(&headers, &config.sensitive_keys_regex)?;
+    let CapturedBody {
+        value: parsed_body,
+        error: body_error,
+        decoded_size,
+    } = parse_captured_body(body, &headers, config);
+    let masked_body = masker.mask_audited(&parsed_body, "response.body", masked_fields);
+    let allowed_headers = filter_allowed_headers(&headers, &config.header_allowlist);
+    let masked_headers =
+        masker.mask_headers_audited(&allowed_headers, "response.headers", masked_fields);
 
     Ok(ResponseInfo {
         headers: masked_headers,
         code: status,
-        size: body.len(),
+        size: wire_size(body, &headers),
+        decoded_size,
         load_time: start_time.elapsed().as_secs_f64(),
         body: serde_json::to_value(masked_body).ok(),
+        body_error,
+        streaming: streaming::is_streaming_response(&headers),
     })
 }
 
+/// A body as parsed for the payload.
+struct CapturedBody {
+    value: Value,
+    /// Why the body could not be decoded, when `value` is `Value::Null`.
+    error: Option<String>,
+    /// The size after undoing `Content-Encoding`, when the body was compressed.
+    decoded_size: Option<usize>,
+}
+
+/// Parses a body if its content type is listed in `captureContentTypes`.
+///
+/// Compressed bodies are decoded according to `Content-Encoding` first; the raw bytes
+/// are left untouched.
+///
+/// # Returns
+///
+/// Returns the parsed body and, if it could not be decoded, the reason why. Bodies whose
+/// content type is not captured are reported as `Value::Null` without a reason.
+fn parse_captured_body(
+    body: &[u8],
+    headers: &std::collections::HashMap<String, String>,
+    config: &Config,
+) -> CapturedBody {
+    let mut captured = CapturedBody {
+        value: Value::Null,
+        error: None,
+        decoded_size: None,
+    };
+    let content_type = get_header_value(headers, "Content-Type").unwrap_or_default();
+
+    if !captures_body(&content_type, config) {
+        return captured;
+    }
+
+    let content_encoding = get_header_value(headers, "Content-Encoding").unwrap_or_default();
+
+    let parsed =
+        decode_body(body, &content_encoding, config.max_decompressed_bytes).and_then(|decoded| {
+            if let Cow::Owned(decoded) = &decoded {
+                captured.decoded_size = Some(decoded.len());
+            }
+            try_parse_body(&decoded, &content_type)
+        });
+
+    match parsed {
+        Ok(value) => captured.value = value,
+        Err(e) => {
+            log(LogLevel::Warn, &format!("Body could not be decoded: {}", e));
+            captured.error = Some(e.to_string());
+        }
+    }
+
+    captured
+}
+
+/// Checks if bodies of a content type are captured.
+///
+/// GraphQL documents are text, but they are captured like JSON bodies so their operation
+/// is detected and their arguments are masked.
+///
+/// # Arguments
+///
+/// * `content_type` - The value of the `Content-Type` header.
+/// * `config` - The configuration holding `captureContentTypes`.
+pub fn captures_body(content_type: &str, config: &Config) -> bool {
+    config
+        .capture_content_types
+        .contains(&BodyKind::from_content_type(content_type))
+        || graphql::is_graphql(content_type)
+}
+
+/// A body read in chunks, kept for parsing only while it fits within a limit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadBody {
+    bytes: Vec<u8>,
+    size: usize,
+    limit: usize,
+    truncated: bool,
+}
+
+impl ReadBody {
+    /// Creates an empty `ReadBody` keeping up to `limit` bytes; `0` keeps any size.
+    pub fn new(limit: usize) -> Self {
+        ReadBody {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Counts a chunk, dropping the kept bytes once the body outgrows the limit.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.size += chunk.len();
+
+        if self.truncated {
+            return;
+        }
+        if self.limit > 0 && self.size > self.limit {
+            self.truncated = true;
+            self.bytes = Vec::new();
+        } else {
+            self.bytes.extend_from_slice(chunk);
+        }
+    }
+
+    /// Returns the body to parse, empty if it outgrew the limit.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the exact size of the body.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Checks if the body outgrew the limit and was not kept.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// Returns the size of a body as sent on the wire.
+///
+/// A body that was read is counted exactly; otherwise `Content-Length` is used, and a
+/// body without one (e.g. a chunked stream that was not buffered) counts as `0`.
+fn wire_size(body: &[u8], headers: &std::collections::HashMap<String, String>) -> usize {
+    if !body.is_empty() {
+        return body.len();
+    }
+
+    get_header_value(headers, "Content-Length")
+        .and_then(|length| length.trim().parse().ok())
+        .unwrap_or(0)
+}
+
 /// Parses a JSON body, returning a null value if parsing fails.
 ///
 /// # Arguments
@@ -91,12 +279,15 @@ pub fn parse_response(
 /// # Returns
 ///
 /// Returns a `Value` representing the parsed JSON, or `Value::Null` if parsing fails.
+#[allow(dead_code)]
 pub fn parse_json_body(body: &[u8]) -> Value {
     serde_json::from_slice(body).unwrap_or(Value::Null)
 }
 
 /// Masks sensitive data in a JSON value based on a regex pattern.
 ///
+/// Shorthand for [`Masker::mask_audited`] without nested JSON inspection.
+///
 /// # Arguments
 ///
 /// * `data` - The JSON value to mask.
@@ -106,34 +297,14 @@ pub fn parse_json_body(body: &[u8]) -> Value {
 ///
 /// Returns a `Result<Value>` containing the masked JSON value,
 /// or an error if the regex pattern is invalid.
+#[allow(dead_code)]
 pub fn mask_sensitive_data(data: &Value, sensitive_keys_regex: &str) -> Result<Value> {
-    let re = Regex::new(sensitive_keys_regex).map_err(|e| TreblleError::Regex(e))?;
-
-    Ok(match data {
-        Value::Object(map) => {
-            let mut new_map = Map::new();
-            for (key, value) in map {
-                if re.is_match(key) {
-                    new_map.insert(key.clone(), Value::String("*****".to_string()));
-                } else {
-                    new_map.insert(
-                        key.clone(),
-                        mask_sensitive_data(value, sensitive_keys_regex)?,
-                    );
-                }
-            }
-            Value::Object(new_map)
-        }
-        Value::Array(arr) => Value::Array(
-            arr.iter()
-                .map(|v| mask_sensitive_data(v, sensitive_keys_regex))
-                .collect::<Result<Vec<_>>>()?,
-        ),
-        _ => data.clone(),
-    })
+    Ok(Masker::new(sensitive_keys_regex)?.mask_audited(data, "", &mut Vec::new()))
 }
 
-/// Masks sensitive headers based on a regex pattern.
+/// Masks sensitive headers based on a regex pattern and the built-in credential header policy.
+///
+/// Shorthand for [`Masker::mask_headers_audited`].
 ///
 /// # Arguments
 ///
@@ -144,41 +315,87 @@ pub fn mask_sensitive_data(data: &Value, sensitive_keys_regex: &str) -> Result<V
 ///
 /// Returns a `Result<HashMap<String, String>>` containing the masked headers,
 /// or an error if the regex pattern is invalid.
+#[allow(dead_code)]
 pub fn mask_sensitive_headers(
     headers: &std::collections::HashMap<String, String>,
     sensitive_keys_regex: &str,
 ) -> Result<std::collections::HashMap<String, String>> {
-    let re = Regex::new(sensitive_keys_regex).map_err(|e| TreblleError::Regex(e))?;
+    Ok(Masker::new(sensitive_keys_regex)?.mask_headers_audited(headers, "", &mut Vec::new()))
+}
 
-    Ok(headers
+/// Keeps only the headers listed in the allowlist.
+///
+/// Header names are compared case-insensitively. An empty allowlist keeps every header.
+///
+/// # Arguments
+///
+/// * `headers` - A map of headers to filter.
+/// * `allowlist` - The header names that may be reported.
+///
+/// # Returns
+///
+/// Returns a `HashMap<String, String>` containing only the permitted headers.
+pub fn filter_allowed_headers(
+    headers: &std::collections::HashMap<String, String>,
+    allowlist: &[String],
+) -> std::collections::HashMap<String, String> {
+    if allowlist.is_empty() {
+        return headers.clone();
+    }
+
+    headers
         .iter()
-        .map(|(key, value)| {
-            if re.is_match(key) {
-                (key.clone(), "*****".to_string())
-            } else {
-                (key.clone(), value.clone())
-            }
+        .filter(|(key, _)| {
+            allowlist
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(key))
         })
-        .collect())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
 }
 
-/// Extracts IP address from headers.
+/// Looks up a header value by name, ignoring case.
 ///
 /// # Arguments
 ///
-/// * `headers` - A map of headers to search for IP information.
+/// * `headers` - A map of headers to search.
+/// * `name` - The header name to look up.
 ///
 /// # Returns
 ///
-/// Returns an `Option<String>` containing the extracted IP address,
-/// or `None` if no IP address is found.
-pub fn extract_ip_from_headers(
+/// Returns an `Option<String>` containing the header value, or `None` if the header is absent.
+pub fn get_header_value(
     headers: &std::collections::HashMap<String, String>,
+    name: &str,
 ) -> Option<String> {
     headers
-        .get("X-Forwarded-For")
-        .or_else(|| headers.get("X-Real-IP"))
-        .map(|ip| ip.split(',').next().unwrap_or("").trim().to_string())
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+/// Fills a buffer with random bytes from the WASI random source.
+///
+/// # Returns
+///
+/// Returns `len` random bytes, or `TreblleError::HostFunction` if the host provides no
+/// randomness.
+pub fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| TreblleError::HostFunction(format!("No randomness available: {}", e)))?;
+
+    Ok(bytes)
+}
+
+/// Formats bytes as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, b| {
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        })
 }
 
 /// Checks if a content type is JSON.
@@ -190,9 +407,9 @@ pub fn extract_ip_from_headers(
 /// # Returns
 ///
 /// Returns `true` if the content type indicates JSON, `false` otherwise.
-
+#[allow(dead_code)]
 pub fn is_json(content_type: &str) -> bool {
-    content_type.to_lowercase().contains("application/json")
+    crate::body_parser::is_json(content_type)
 }
 
 #[cfg(test)]
@@ -206,6 +423,185 @@ mod tests {
         assert!(!is_json("text/plain"));
     }
 
+    #[test]
+    fn test_parse_request_masks_form_fields() -> Result<()> {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        );
+
+        let mut masked_fields = Vec::new();
+        let request = parse_request(
+            "POST".to_string(),
+            "/login".to_string(),
+            headers,
+            b"username=john&password=hunter2",
+            None,
+            &Config::fallback(),
+            &Masker::default(),
+            &mut masked_fields,
+        )?;
+
+        let body = request.body.expect("body should be captured");
+        assert_eq!(body["username"], "john");
+        assert_eq!(body["password"], "*****");
+        assert_eq!(masked_fields.len(), 1);
+        assert_eq!(masked_fields[0].path, "request.body.password");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_request_masks_json_fields() -> Result<()> {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Content-Type".to_string(), "application/json".to_string());
+        headers.insert("Authorization".to_string(), "Bearer token123".to_string());
+
+        let config = Config {
+            sensitive_keys_regex: "(?i)password|email".to_string(),
+            ..Config::fallback()
+        };
+        let request = parse_request(
+            "POST".to_string(),
+            "/users".to_string(),
+            headers,
+            br#"{"username":"john_doe","password":"secret123","email":"john@example.com"}"#,
+            None,
+            &config,
+            &Masker::from_config(&config)?,
+            &mut Vec::new(),
+        )?;
+
+        let body = request.body.expect("body should be captured");
+        assert_eq!(body["username"], "john_doe");
+        assert_eq!(body["password"], "*****");
+        assert_eq!(body["email"], "*****");
+        assert_eq!(request.headers["Authorization"], "Bearer *****");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_request_without_body() -> Result<()> {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("User-Agent".to_string(), "curl/8.0".to_string());
+
+        let request = parse_request(
+            "GET".to_string(),
+            "/users?page=2".to_string(),
+            headers,
+            b"",
+            Some("203.0.113.7".to_string()),
+            &Config::fallback(),
+            &Masker::default(),
+            &mut Vec::new(),
+        )?;
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.url, "/users?page=2");
+        assert_eq!(request.user_agent, "curl/8.0");
+        assert_eq!(request.ip, "203.0.113.7");
+        assert_eq!(request.body, Some(Value::Null));
+        assert_eq!(request.body_error, None);
+        assert_eq!(request.size, 0);
+        assert_eq!(request.decoded_size, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_body_keeps_only_bodies_within_limit() {
+        let mut body = ReadBody::new(8);
+        body.push(b"abcd");
+        body.push(b"efgh");
+        assert_eq!(body.bytes(), b"abcdefgh");
+        assert!(!body.is_truncated());
+
+        body.push(b"ij");
+        body.push(b"klmnop");
+        assert_eq!(body.bytes(), b"");
+        assert_eq!(body.size(), 16);
+        assert!(body.is_truncated());
+
+        let mut unlimited = ReadBody::new(0);
+        unlimited.push(&[0; 64]);
+        assert_eq!(unlimited.bytes().len(), 64);
+    }
+
+    #[test]
+    fn test_wire_size() {
+        let mut headers = std::collections::HashMap::new();
+        assert_eq!(wire_size(b"", &headers), 0);
+
+        headers.insert("content-length".to_string(), "1024".to_string());
+        assert_eq!(wire_size(b"", &headers), 1024);
+        assert_eq!(wire_size(b"exact", &headers), 5);
+    }
+
+    #[test]
+    fn test_parse_response_decodes_gzip_body() -> Result<()> {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(br#"{"password":"hunter2","ok":true}"#)?;
+        let compressed = encoder.finish()?;
+
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Content-Type".to_string(), "application/json".to_string());
+        headers.insert("Content-Encoding".to_string(), "gzip".to_string());
+
+        let response = parse_response(
+            200,
+            headers,
+            &compressed,
+            Instant::now(),
+            &Config::fallback(),
+            &Masker::default(),
+            &mut Vec::new(),
+        )?;
+
+        let body = response.body.expect("body should be captured");
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["password"], "*****");
+        assert_eq!(response.size, compressed.len());
+        assert_eq!(response.decoded_size, Some(32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_request_graphql_operation_as_route() -> Result<()> {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Content-Type".to_string(), "application/json".to_string());
+
+        let config = Config {
+            graphql_operation_as_route: true,
+            ..Config::fallback()
+        };
+        let request = parse_request(
+            "POST".to_string(),
+            "/graphql".to_string(),
+            headers,
+            br#"{"query":"mutation Login($password: String!) { login(password: $password) }","variables":{"password":"hunter2"}}"#,
+            None,
+            &config,
+            &Masker::from_config(&config)?,
+            &mut Vec::new(),
+        )?;
+
+        let graphql = request
+            .graphql
+            .expect("GraphQL operation should be detected");
+        assert_eq!(graphql.operation_type, "mutation");
+        assert_eq!(graphql.operation_name, Some("Login".to_string()));
+        assert_eq!(request.route_path, Some("/graphql/Login".to_string()));
+        assert_eq!(request.body.unwrap()["variables"]["password"], "*****");
+
+        Ok(())
+    }
+
     #[test]
     fn test_mask_sensitive_data() -> Result<()> {
         let data = serde_json::json!({
@@ -236,6 +632,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_filter_allowed_headers() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Content-Type".to_string(), "application/json".to_string());
+        headers.insert("X-Internal".to_string(), "secret".to_string());
+
+        let filtered = filter_allowed_headers(&headers, &["content-type".to_string()]);
+
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered["Content-Type"], "application/json");
+        assert_eq!(filter_allowed_headers(&headers, &[]).len(), 2);
+    }
+
     #[test]
     fn test_invalid_regex() {
         let data = serde_json::json!({"key": "value"});